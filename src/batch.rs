@@ -0,0 +1,198 @@
+//! Micro-batching of frames into a single broker message.
+//!
+//! High-rate feeds produce one wRPC call per frame, so frames can be coalesced
+//! by count, size and age and delivered as one `handle-message` call.
+
+use std::time::Duration;
+
+use crate::config::{BatchFormat, ConnectionConfig, EnvelopeFormat};
+use crate::metadata::encode_envelope_array;
+use crate::stream::Frame;
+
+/// Wait for a partial batch when `batch_max_delay_ms` is unset
+const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(20);
+
+/// Accumulates frames until a batch is complete
+#[derive(Debug)]
+pub struct Batcher {
+    max_frames: usize,
+    max_bytes: usize,
+    max_delay: Duration,
+    frames: Vec<Frame>,
+    bytes: usize,
+}
+
+impl Batcher {
+    /// Create a batcher from the link's batch limits
+    pub fn new(config: &ConnectionConfig) -> Self {
+        Batcher {
+            max_frames: match config.batch_max_frames {
+                0 => usize::MAX,
+                frames => frames,
+            },
+            max_bytes: config.batch_max_bytes,
+            max_delay: match config.batch_max_delay_ms {
+                0 => DEFAULT_MAX_DELAY,
+                delay => Duration::from_millis(delay),
+            },
+            frames: Vec::new(),
+            bytes: 0,
+        }
+    }
+
+    /// How long a partial batch may wait after its first frame
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    /// Whether no frames are pending
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Add a frame, returning any batches completed by it.
+    ///
+    /// A pending batch is closed first if the frame has a different subject or
+    /// would push it past `max_bytes`; the batch holding the frame is returned
+    /// as soon as it reaches `max_frames` or `max_bytes`.
+    pub fn push(&mut self, frame: Frame) -> Vec<Vec<Frame>> {
+        let mut complete = Vec::new();
+        let subject_changed = self
+            .frames
+            .first()
+            .is_some_and(|first| first.subject != frame.subject);
+        let too_big = self.max_bytes > 0 && self.bytes + frame.data.len() > self.max_bytes;
+        if !self.frames.is_empty() && (subject_changed || too_big) {
+            complete.push(self.take());
+        }
+
+        self.bytes += frame.data.len();
+        self.frames.push(frame);
+        if self.frames.len() >= self.max_frames
+            || (self.max_bytes > 0 && self.bytes >= self.max_bytes)
+        {
+            complete.push(self.take());
+        }
+        complete
+    }
+
+    /// Remove and return the pending frames
+    pub fn take(&mut self) -> Vec<Frame> {
+        self.bytes = 0;
+        std::mem::take(&mut self.frames)
+    }
+}
+
+/// Encode a batch into one body.
+///
+/// `item` produces the per-frame body (raw or envelope) used by the
+/// newline-joined and length-prefixed formats; the envelope array format
+/// always wraps each frame in an envelope.
+pub fn encode_batch<F>(
+    frames: &[Frame],
+    format: BatchFormat,
+    envelope: EnvelopeFormat,
+    mut item: F,
+) -> anyhow::Result<Vec<u8>>
+where
+    F: FnMut(&Frame) -> anyhow::Result<Vec<u8>>,
+{
+    match format {
+        BatchFormat::Lines => {
+            let mut out = Vec::new();
+            for (i, frame) in frames.iter().enumerate() {
+                if i > 0 {
+                    out.push(b'\n');
+                }
+                out.extend(item(frame)?);
+            }
+            Ok(out)
+        }
+        BatchFormat::LengthPrefixed => {
+            let mut out = Vec::new();
+            for frame in frames {
+                let body = item(frame)?;
+                out.extend(u32::try_from(body.len())?.to_be_bytes());
+                out.extend(body);
+            }
+            Ok(out)
+        }
+        BatchFormat::EnvelopeArray => encode_envelope_array(frames, envelope),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batcher(max_frames: usize, max_bytes: usize) -> Batcher {
+        Batcher::new(&ConnectionConfig {
+            batch_max_frames: max_frames,
+            batch_max_bytes: max_bytes,
+            ..Default::default()
+        })
+    }
+
+    fn data(batch: &[Frame]) -> Vec<&[u8]> {
+        batch.iter().map(|f| f.data.as_slice()).collect()
+    }
+
+    #[test]
+    fn test_batcher_limits() {
+        let mut b = batcher(3, 0);
+        assert!(b.push(b"a".to_vec().into()).is_empty());
+        assert!(b.push(b"b".to_vec().into()).is_empty());
+        let done = b.push(b"c".to_vec().into());
+        assert_eq!(data(&done[0]), vec![b"a", b"b", b"c"]);
+        assert!(b.is_empty());
+
+        let mut b = batcher(10, 4);
+        assert!(b.push(b"abc".to_vec().into()).is_empty());
+        let done = b.push(b"de".to_vec().into());
+        assert_eq!(done.len(), 1);
+        assert_eq!(data(&done[0]), vec![b"abc"]);
+        assert_eq!(data(&b.take()), vec![b"de"]);
+    }
+
+    #[test]
+    fn test_batcher_splits_on_subject() {
+        let mut b = batcher(10, 0);
+        b.push(b"a".to_vec().into());
+        let done = b.push(Frame {
+            data: b"b".to_vec(),
+            subject: Some("other".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(data(&done[0]), vec![b"a"]);
+        assert_eq!(b.take()[0].subject.as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn test_encode_batch_formats() {
+        let frames: Vec<Frame> = vec![b"ab".to_vec().into(), b"c".to_vec().into()];
+        let raw = |f: &Frame| Ok(f.data.clone());
+
+        let lines = encode_batch(&frames, BatchFormat::Lines, EnvelopeFormat::None, raw).unwrap();
+        assert_eq!(lines, b"ab\nc");
+
+        let prefixed = encode_batch(
+            &frames,
+            BatchFormat::LengthPrefixed,
+            EnvelopeFormat::None,
+            raw,
+        )
+        .unwrap();
+        assert_eq!(prefixed, b"\0\0\0\x02ab\0\0\0\x01c");
+
+        let array = encode_batch(
+            &frames,
+            BatchFormat::EnvelopeArray,
+            EnvelopeFormat::Json,
+            raw,
+        )
+        .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&array).unwrap();
+        assert_eq!(json[0]["payload"], "YWI=");
+        assert_eq!(json[1]["payload"], "Yw==");
+    }
+}
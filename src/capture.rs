@@ -0,0 +1,607 @@
+//! Frame capture files (`capture_path`) and their replay (`protocol=replay`).
+//!
+//! The `raw` format is the 8-byte magic `TUSCAP01` followed by one record per
+//! frame: `timestamp_us: u64 | len: u32 | data`, big-endian, where the
+//! timestamp is the receive time in microseconds since the Unix epoch.
+//!
+//! `pcapng` files hold a section header, one interface of link type `USER0`
+//! and an enhanced packet block per frame, with the sender address as the
+//! packet comment, so they open directly in Wireshark. The reader accepts
+//! either byte order and any interface timestamp resolution.
+//!
+//! An existing capture file is appended to: a raw file must already be in
+//! raw format, while a pcapng capture starts a new section. A link writes its
+//! capture on a dedicated thread, which flushes frames to the file within
+//! about a second.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader};
+use tracing::{error, warn};
+
+use crate::config::{CaptureFormat, ConnectionConfig};
+
+/// Magic at the start of raw capture files
+const RAW_MAGIC: &[u8; 8] = b"TUSCAP01";
+
+/// Size of a raw record header: timestamp and length
+const RAW_HEADER_LEN: usize = 12;
+
+/// pcapng section header block type; the same in either byte order
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+
+/// pcapng interface description block type
+const PCAPNG_IDB: u32 = 1;
+
+/// pcapng enhanced packet block type
+const PCAPNG_EPB: u32 = 6;
+
+/// pcapng byte-order magic
+const PCAPNG_BYTE_ORDER: u32 = 0x1A2B_3C4D;
+
+/// `LINKTYPE_USER0`: frames are application payloads, not packets
+const LINKTYPE_USER0: u16 = 147;
+
+/// `opt_comment` option code
+const OPT_COMMENT: u16 = 1;
+
+/// `if_tsresol` option code
+const OPT_IF_TSRESOL: u16 = 9;
+
+/// Largest pcapng block or raw frame the reader accepts
+const MAX_RECORD_LEN: usize = 256 * 1024 * 1024;
+
+/// Buffer size of a capture file writer
+const WRITE_BUFFER_LEN: usize = 64 * 1024;
+
+/// Longest a captured frame stays buffered before it is flushed to the file
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Frames queued for a capture thread before further frames are dropped
+const CAPTURE_QUEUE_LEN: usize = 4096;
+
+/// A frame read back from a capture file
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedFrame {
+    /// Receive time in microseconds since the Unix epoch
+    pub timestamp_us: u64,
+    pub data: Vec<u8>,
+}
+
+/// A frame queued for a capture thread
+type QueuedFrame = (Vec<u8>, SystemTime, Option<String>);
+
+/// Hands a link's frames to a [`CaptureWriter`] on its own thread, so the
+/// receive loop never waits on the disk. Frames are dropped, with a warning,
+/// while the thread is [`CAPTURE_QUEUE_LEN`] frames behind.
+///
+/// Dropping it detaches the thread, which still writes and flushes the queued
+/// frames before it exits; [`CaptureThread::close`] also waits for that.
+#[derive(Debug)]
+pub struct CaptureThread {
+    frames: SyncSender<QueuedFrame>,
+    thread: JoinHandle<()>,
+    dropping: bool,
+}
+
+impl CaptureThread {
+    /// Open the link's capture file and start its thread, or `None` if
+    /// `capture_path` is unset
+    pub fn from_config(config: &ConnectionConfig) -> io::Result<Option<Self>> {
+        if config.capture_path.is_empty() {
+            return Ok(None);
+        }
+        let writer = CaptureWriter::open(Path::new(&config.capture_path), config.capture_format)?;
+        CaptureThread::spawn(writer).map(Some)
+    }
+
+    /// Start a thread writing frames to `writer`. It flushes the file once
+    /// [`FLUSH_INTERVAL`] passes without a new frame, and when it stops.
+    pub fn spawn(mut writer: CaptureWriter) -> io::Result<Self> {
+        let (frames, queue) = mpsc::sync_channel::<QueuedFrame>(CAPTURE_QUEUE_LEN);
+        let thread = std::thread::Builder::new()
+            .name("capture-writer".to_string())
+            .spawn(move || {
+                let mut last_flush = std::time::Instant::now();
+                let result = loop {
+                    let written = match queue.recv_timeout(FLUSH_INTERVAL) {
+                        Ok((data, at, peer)) => writer.write(&data, at, peer.as_deref()),
+                        Err(RecvTimeoutError::Timeout) => Ok(()),
+                        Err(RecvTimeoutError::Disconnected) => break writer.flush(),
+                    };
+                    let written = written.and_then(|()| {
+                        if last_flush.elapsed() < FLUSH_INTERVAL {
+                            return Ok(());
+                        }
+                        last_flush = std::time::Instant::now();
+                        writer.flush()
+                    });
+                    if written.is_err() {
+                        break written;
+                    }
+                };
+                if let Err(e) = result {
+                    error!(error = %e, "failed to write capture file, capture stopped");
+                }
+            })?;
+        Ok(CaptureThread {
+            frames,
+            thread,
+            dropping: false,
+        })
+    }
+
+    /// Queue a frame received at `at` from `peer`. Returns `false` once the
+    /// thread has stopped after a write error.
+    pub fn write(&mut self, data: &[u8], at: SystemTime, peer: Option<&str>) -> bool {
+        match self
+            .frames
+            .try_send((data.to_vec(), at, peer.map(str::to_string)))
+        {
+            Ok(()) => self.dropping = false,
+            Err(TrySendError::Full(_)) => {
+                if !self.dropping {
+                    warn!("capture file writes are falling behind, dropping captured frames");
+                }
+                self.dropping = true;
+            }
+            Err(TrySendError::Disconnected(_)) => return false,
+        }
+        true
+    }
+
+    /// Stop the thread and wait, on the blocking pool, until it has written
+    /// and flushed every queued frame
+    pub async fn close(self) {
+        drop(self.frames);
+        let thread = self.thread;
+        let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+    }
+}
+
+/// Appends received frames to a capture file
+#[derive(Debug)]
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+    format: CaptureFormat,
+}
+
+impl CaptureWriter {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: &Path, format: CaptureFormat) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        if len > 0 {
+            let mut head = [0u8; 8];
+            let n = file.read_at(&mut head, 0)?;
+            if detect_format(&head[..n]) != Some(format) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not a {format:?} capture file", path.display()),
+                ));
+            }
+        }
+
+        let mut writer = CaptureWriter {
+            file: BufWriter::with_capacity(WRITE_BUFFER_LEN, file),
+            format,
+        };
+        match format {
+            CaptureFormat::Raw if len == 0 => writer.file.write_all(RAW_MAGIC)?,
+            CaptureFormat::Raw => {}
+            CaptureFormat::Pcapng => writer.write_pcapng_header()?,
+        }
+        writer.file.flush()?;
+        Ok(writer)
+    }
+
+    /// Append a frame received at `at` from `peer`. It is buffered until the
+    /// next [`CaptureWriter::flush`] or the writer is dropped.
+    pub fn write(&mut self, data: &[u8], at: SystemTime, peer: Option<&str>) -> io::Result<()> {
+        let timestamp_us = at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        let len = u32::try_from(data.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "frame too large to capture")
+        })?;
+        match self.format {
+            CaptureFormat::Raw => {
+                self.file.write_all(&timestamp_us.to_be_bytes())?;
+                self.file.write_all(&len.to_be_bytes())?;
+                self.file.write_all(data)?;
+            }
+            CaptureFormat::Pcapng => {
+                let comment = peer.map(str::as_bytes).unwrap_or_default();
+                let options = if comment.is_empty() {
+                    0
+                } else {
+                    4 + padded(comment.len()) + 4
+                };
+                let total = 32 + padded(data.len()) + options;
+                self.write_block_start(PCAPNG_EPB, total)?;
+                self.file.write_all(&0u32.to_le_bytes())?;
+                self.file
+                    .write_all(&((timestamp_us >> 32) as u32).to_le_bytes())?;
+                self.file.write_all(&(timestamp_us as u32).to_le_bytes())?;
+                self.file.write_all(&len.to_le_bytes())?;
+                self.file.write_all(&len.to_le_bytes())?;
+                self.write_padded(data)?;
+                if !comment.is_empty() {
+                    self.file.write_all(&OPT_COMMENT.to_le_bytes())?;
+                    self.file.write_all(&(comment.len() as u16).to_le_bytes())?;
+                    self.write_padded(comment)?;
+                    self.file.write_all(&[0; 4])?;
+                }
+                self.file.write_all(&(total as u32).to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write buffered frames to the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Start a section with a single `USER0` interface in microseconds
+    fn write_pcapng_header(&mut self) -> io::Result<()> {
+        self.write_block_start(PCAPNG_SHB, 28)?;
+        self.file.write_all(&PCAPNG_BYTE_ORDER.to_le_bytes())?;
+        self.file.write_all(&1u16.to_le_bytes())?;
+        self.file.write_all(&0u16.to_le_bytes())?;
+        self.file.write_all(&(-1i64).to_le_bytes())?;
+        self.file.write_all(&28u32.to_le_bytes())?;
+
+        self.write_block_start(PCAPNG_IDB, 20)?;
+        self.file.write_all(&LINKTYPE_USER0.to_le_bytes())?;
+        self.file.write_all(&0u16.to_le_bytes())?;
+        self.file.write_all(&0u32.to_le_bytes())?;
+        self.file.write_all(&20u32.to_le_bytes())
+    }
+
+    fn write_block_start(&mut self, block_type: u32, total: usize) -> io::Result<()> {
+        self.file.write_all(&block_type.to_le_bytes())?;
+        self.file.write_all(&(total as u32).to_le_bytes())
+    }
+
+    fn write_padded(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.file
+            .write_all(&[0; 3][..padded(data.len()) - data.len()])
+    }
+}
+
+/// Reads frames back from a capture file of either format
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    reader: R,
+    format: CaptureFormat,
+    /// Whether the current pcapng section is big-endian
+    big_endian: bool,
+    /// Timestamp units per second of each interface in the current section
+    interfaces: Vec<u64>,
+}
+
+impl CaptureReader<BufReader<tokio::fs::File>> {
+    /// Open a capture file, detecting its format
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        CaptureReader::new(BufReader::new(tokio::fs::File::open(path).await?)).await
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> CaptureReader<R> {
+    /// Detect the format of a capture and position the reader at its first
+    /// frame
+    pub async fn new(mut reader: R) -> io::Result<Self> {
+        let head = reader.fill_buf().await?;
+        let Some(format) = detect_format(&head[..head.len().min(8)]) else {
+            return Err(invalid("not a raw or pcapng capture file"));
+        };
+        if format == CaptureFormat::Raw {
+            reader.consume(RAW_MAGIC.len());
+        }
+        Ok(CaptureReader {
+            reader,
+            format,
+            big_endian: false,
+            interfaces: Vec::new(),
+        })
+    }
+
+    /// Format of the capture file
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    /// Read the next frame, or `None` at the end of the file
+    pub async fn next(&mut self) -> io::Result<Option<CapturedFrame>> {
+        match self.format {
+            CaptureFormat::Raw => self.next_raw().await,
+            CaptureFormat::Pcapng => self.next_pcapng().await,
+        }
+    }
+
+    async fn next_raw(&mut self) -> io::Result<Option<CapturedFrame>> {
+        if self.reader.fill_buf().await?.is_empty() {
+            return Ok(None);
+        }
+        let mut header = [0u8; RAW_HEADER_LEN];
+        self.reader.read_exact(&mut header).await?;
+        let timestamp_us = u64::from_be_bytes(header[..8].try_into().unwrap());
+        let len = u32::from_be_bytes(header[8..].try_into().unwrap()) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(invalid("capture frame too large"));
+        }
+        let mut data = vec![0u8; len];
+        self.reader.read_exact(&mut data).await?;
+        Ok(Some(CapturedFrame { timestamp_us, data }))
+    }
+
+    async fn next_pcapng(&mut self) -> io::Result<Option<CapturedFrame>> {
+        loop {
+            if self.reader.fill_buf().await?.is_empty() {
+                return Ok(None);
+            }
+            let mut header = [0u8; 8];
+            self.reader.read_exact(&mut header).await?;
+            if header[..4] == PCAPNG_SHB.to_le_bytes() {
+                let mut magic = [0u8; 4];
+                self.reader.read_exact(&mut magic).await?;
+                self.big_endian = if magic == PCAPNG_BYTE_ORDER.to_be_bytes() {
+                    true
+                } else if magic == PCAPNG_BYTE_ORDER.to_le_bytes() {
+                    false
+                } else {
+                    return Err(invalid("bad pcapng byte-order magic"));
+                };
+                self.interfaces.clear();
+                let total = self.block_len(&header)?;
+                self.read_body(total - 12).await?;
+                continue;
+            }
+
+            let block_type = self.u32(&header[..4]);
+            let total = self.block_len(&header)?;
+            let body = self.read_body(total - 8).await?;
+            let body = &body[..body.len() - 4];
+            match block_type {
+                PCAPNG_IDB if body.len() >= 8 => {
+                    let units = self
+                        .options(&body[8..])
+                        .find(|(code, _)| *code == OPT_IF_TSRESOL)
+                        .and_then(|(_, value)| value.first().copied())
+                        .map_or(1_000_000, tsresol_units);
+                    self.interfaces.push(units);
+                }
+                PCAPNG_EPB if body.len() >= 20 => {
+                    let interface = self.u32(&body[..4]) as usize;
+                    let timestamp =
+                        u64::from(self.u32(&body[4..8])) << 32 | u64::from(self.u32(&body[8..12]));
+                    let len = self.u32(&body[12..16]) as usize;
+                    let Some(data) = body.get(20..20 + len) else {
+                        return Err(invalid("pcapng packet longer than its block"));
+                    };
+                    let units = self.interfaces.get(interface).copied().unwrap_or(1_000_000);
+                    let timestamp_us =
+                        (u128::from(timestamp) * 1_000_000 / u128::from(units)) as u64;
+                    return Ok(Some(CapturedFrame {
+                        timestamp_us,
+                        data: data.to_vec(),
+                    }));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    /// Total length of the block starting with `header`
+    fn block_len(&self, header: &[u8; 8]) -> io::Result<usize> {
+        let total = self.u32(&header[4..]) as usize;
+        if total < 16 || !total.is_multiple_of(4) || total > MAX_RECORD_LEN {
+            return Err(invalid("bad pcapng block length"));
+        }
+        Ok(total)
+    }
+
+    async fn read_body(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut body = vec![0u8; len];
+        self.reader.read_exact(&mut body).await?;
+        Ok(body)
+    }
+
+    /// Iterate the `(code, value)` pairs of a pcapng options list
+    fn options<'a>(&'a self, mut data: &'a [u8]) -> impl Iterator<Item = (u16, &'a [u8])> + 'a {
+        std::iter::from_fn(move || {
+            if data.len() < 4 {
+                return None;
+            }
+            let code = self.u16(&data[..2]);
+            let len = self.u16(&data[2..4]) as usize;
+            let value = data.get(4..4 + len)?;
+            data = data.get(4 + padded(len)..).unwrap_or_default();
+            (code != 0).then_some((code, value))
+        })
+    }
+}
+
+/// Format of a capture file from its first bytes
+fn detect_format(head: &[u8]) -> Option<CaptureFormat> {
+    if head == RAW_MAGIC {
+        Some(CaptureFormat::Raw)
+    } else if head.starts_with(&PCAPNG_SHB.to_le_bytes()) {
+        Some(CaptureFormat::Pcapng)
+    } else {
+        None
+    }
+}
+
+/// Timestamp units per second for an `if_tsresol` value
+fn tsresol_units(tsresol: u8) -> u64 {
+    let exponent = u32::from(tsresol & 0x7f).min(19);
+    if tsresol & 0x80 == 0 {
+        10u64.pow(exponent)
+    } else {
+        1u64 << exponent.min(63)
+    }
+}
+
+/// `len` rounded up to a multiple of 4
+fn padded(len: usize) -> usize {
+    len.div_ceil(4) * 4
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("tcp-udp-capture-{}-{name}", std::process::id()))
+    }
+
+    async fn read_all(path: &Path) -> (CaptureFormat, Vec<CapturedFrame>) {
+        let mut reader = CaptureReader::open(path).await.unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.next().await.unwrap() {
+            frames.push(frame);
+        }
+        (reader.format(), frames)
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_and_append() {
+        let at = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        for format in [CaptureFormat::Raw, CaptureFormat::Pcapng] {
+            let path = temp_path(&format!("{format:?}"));
+            let _ = std::fs::remove_file(&path);
+
+            let mut writer = CaptureWriter::open(&path, format).unwrap();
+            writer.write(b"first", at, Some("10.0.0.1:9000")).unwrap();
+            writer.write(b"", at, None).unwrap();
+            drop(writer);
+            // Reopening appends, in a new section for pcapng
+            let later = at + Duration::from_millis(250);
+            let mut writer = CaptureWriter::open(&path, format).unwrap();
+            writer.write(b"second frame", later, None).unwrap();
+            drop(writer);
+
+            let (detected, frames) = read_all(&path).await;
+            assert_eq!(detected, format);
+            let frames: Vec<_> = frames
+                .into_iter()
+                .map(|f| (f.timestamp_us, f.data))
+                .collect();
+            assert_eq!(
+                frames,
+                vec![
+                    (1_700_000_000_123_456, b"first".to_vec()),
+                    (1_700_000_000_123_456, vec![]),
+                    (1_700_000_000_373_456, b"second frame".to_vec()),
+                ],
+                "{format:?}"
+            );
+
+            let other = match format {
+                CaptureFormat::Raw => CaptureFormat::Pcapng,
+                CaptureFormat::Pcapng => CaptureFormat::Raw,
+            };
+            assert!(CaptureWriter::open(&path, other).is_err());
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capture_thread_writes_on_close() {
+        let at = UNIX_EPOCH + Duration::from_micros(1_700_000_000_000_000);
+        let path = temp_path("thread");
+        let _ = std::fs::remove_file(&path);
+
+        let writer = CaptureWriter::open(&path, CaptureFormat::Raw).unwrap();
+        let mut thread = CaptureThread::spawn(writer).unwrap();
+        assert!(thread.write(b"one", at, None));
+        assert!(thread.write(b"two", at, Some("10.0.0.1:9000")));
+        thread.close().await;
+
+        let (_, frames) = read_all(&path).await;
+        let data: Vec<_> = frames.into_iter().map(|f| f.data).collect();
+        assert_eq!(data, vec![b"one".to_vec(), b"two".to_vec()]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_big_endian_pcapng_with_nanoseconds() {
+        let mut data = Vec::new();
+        let mut block = |block_type: u32, body: &[u8]| {
+            let total = (12 + body.len()) as u32;
+            data.extend_from_slice(&block_type.to_be_bytes());
+            data.extend_from_slice(&total.to_be_bytes());
+            data.extend_from_slice(body);
+            data.extend_from_slice(&total.to_be_bytes());
+        };
+        let mut shb = PCAPNG_BYTE_ORDER.to_be_bytes().to_vec();
+        shb.extend_from_slice(&[0, 1, 0, 0]);
+        shb.extend_from_slice(&(-1i64).to_be_bytes());
+        block(PCAPNG_SHB, &shb);
+        // Interface with if_tsresol=9 (nanoseconds)
+        let mut idb = vec![0, 147, 0, 0, 0, 0, 0, 0];
+        idb.extend_from_slice(&[0, 9, 0, 1, 9, 0, 0, 0, 0, 0, 0, 0]);
+        block(PCAPNG_IDB, &idb);
+        // A block the reader does not know is skipped
+        block(0x0000_0BAD, &[1, 2, 3, 4]);
+        let timestamp: u64 = 1_500_000_000_000_000_789;
+        let mut epb = 0u32.to_be_bytes().to_vec();
+        epb.extend_from_slice(&((timestamp >> 32) as u32).to_be_bytes());
+        epb.extend_from_slice(&(timestamp as u32).to_be_bytes());
+        epb.extend_from_slice(&3u32.to_be_bytes());
+        epb.extend_from_slice(&3u32.to_be_bytes());
+        epb.extend_from_slice(b"abc\0");
+        block(PCAPNG_EPB, &epb);
+
+        let mut reader = CaptureReader::new(&data[..]).await.unwrap();
+        assert_eq!(reader.format(), CaptureFormat::Pcapng);
+        assert_eq!(
+            reader.next().await.unwrap(),
+            Some(CapturedFrame {
+                timestamp_us: 1_500_000_000_000_000,
+                data: b"abc".to_vec(),
+            })
+        );
+        assert_eq!(reader.next().await.unwrap(), None);
+
+        assert!(CaptureReader::new(&b"not a capture"[..]).await.is_err());
+    }
+}
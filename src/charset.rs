@@ -0,0 +1,138 @@
+//! Transcoding of non-UTF-8 text feeds (`charset=<label>`).
+//!
+//! Labels are WHATWG encoding labels such as `latin1`, `windows-1252` or
+//! `shift_jis`. Only ASCII-compatible encodings are accepted, since frames
+//! are split on `\n` before they are transcoded.
+
+use anyhow::bail;
+use encoding_rs::{DecoderResult, Encoding};
+
+use crate::config::{CharsetInvalid, ConnectionConfig};
+
+/// Converts frames from the link's charset to UTF-8
+#[derive(Debug, Clone, Copy)]
+pub struct Transcoder {
+    encoding: &'static Encoding,
+    invalid: CharsetInvalid,
+}
+
+impl Transcoder {
+    /// Build the transcoder for a link, or `None` if `charset` is unset
+    pub fn from_config(config: &ConnectionConfig) -> anyhow::Result<Option<Self>> {
+        if config.charset.is_empty() {
+            return Ok(None);
+        }
+        let Some(encoding) = Encoding::for_label(config.charset.trim().as_bytes()) else {
+            bail!("unknown charset {:?}", config.charset);
+        };
+        if !encoding.is_ascii_compatible() {
+            bail!(
+                "charset {} is not ASCII-compatible and cannot be line-framed",
+                encoding.name()
+            );
+        }
+        Ok(Some(Transcoder {
+            encoding,
+            invalid: config.charset_invalid,
+        }))
+    }
+
+    /// Canonical name of the source encoding
+    pub fn name(&self) -> &'static str {
+        self.encoding.name()
+    }
+
+    /// Transcode a frame to UTF-8. Invalid sequences become U+FFFD or are
+    /// left out; with [`CharsetInvalid::Error`] the frame yields `None`.
+    pub fn transcode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if self.invalid == CharsetInvalid::Replace {
+            let (text, _) = self.encoding.decode_without_bom_handling(data);
+            return Some(text.into_owned().into_bytes());
+        }
+
+        let mut decoder = self.encoding.new_decoder_without_bom_handling();
+        let mut out = String::with_capacity(
+            decoder
+                .max_utf8_buffer_length_without_replacement(data.len())
+                .unwrap_or(data.len() * 3),
+        );
+        let mut input = data;
+        loop {
+            let (result, read) =
+                decoder.decode_to_string_without_replacement(input, &mut out, true);
+            input = &input[read..];
+            match result {
+                DecoderResult::InputEmpty => return Some(out.into_bytes()),
+                DecoderResult::OutputFull => out.reserve(input.len() * 3 + 4),
+                DecoderResult::Malformed(..) if self.invalid == CharsetInvalid::Skip => {}
+                DecoderResult::Malformed(..) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transcoder(charset: &str, invalid: CharsetInvalid) -> Transcoder {
+        Transcoder::from_config(&ConnectionConfig {
+            charset: charset.to_string(),
+            charset_invalid: invalid,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_single_byte_charsets() {
+        let latin1 = transcoder("latin1", CharsetInvalid::Error);
+        assert_eq!(latin1.name(), "windows-1252");
+        assert_eq!(
+            latin1.transcode(b"caf\xe9 \x80").unwrap(),
+            "café €".as_bytes()
+        );
+        assert_eq!(
+            transcoder("iso-8859-7", CharsetInvalid::Error)
+                .transcode(b"\xe1\xe2")
+                .unwrap(),
+            "αβ".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_invalid_sequences() {
+        // 0x81 0x20 is not a valid Shift_JIS double-byte sequence
+        let data = b"\x93\xfa\x96\x7b \x81\x20ok";
+        assert_eq!(
+            transcoder("shift_jis", CharsetInvalid::Replace)
+                .transcode(data)
+                .unwrap(),
+            "日本 \u{fffd} ok".as_bytes()
+        );
+        assert_eq!(
+            transcoder("sjis", CharsetInvalid::Skip)
+                .transcode(data)
+                .unwrap(),
+            "日本  ok".as_bytes()
+        );
+        assert!(transcoder("shift_jis", CharsetInvalid::Error)
+            .transcode(data)
+            .is_none());
+    }
+
+    #[test]
+    fn test_rejected_labels() {
+        assert!(Transcoder::from_config(&ConnectionConfig::default())
+            .unwrap()
+            .is_none());
+        for charset in ["klingon", "utf-16le"] {
+            assert!(Transcoder::from_config(&ConnectionConfig {
+                charset: charset.to_string(),
+                ..Default::default()
+            })
+            .is_err());
+        }
+    }
+}
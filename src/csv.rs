@@ -0,0 +1,318 @@
+//! Delimited text (CSV) records from legacy instruments.
+//!
+//! Each line is split on the configured delimiter, honouring a quote
+//! character with doubled quotes as escapes, and turned into an object keyed
+//! by column name. Columns come from `csv_columns` (`name` or `name:type`) or
+//! from a header row; fields without a column name are keyed by their
+//! zero-based index.
+
+use anyhow::{bail, Context as _};
+use serde_json::{Map, Value};
+
+use crate::config::{ConnectionConfig, CsvHeader};
+
+/// Type hint for a column's values
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+/// A configured column
+#[derive(Debug, Clone, PartialEq)]
+struct Column {
+    name: String,
+    kind: ColumnType,
+}
+
+/// Result of decoding one line
+#[derive(Debug, PartialEq)]
+pub enum CsvLine {
+    /// A header row, consumed without producing a record
+    Header,
+    /// A record and the value of the subject column, if configured and non-empty
+    Record(Map<String, Value>, Option<String>),
+    /// A line that could not be split or whose fields do not match their types
+    Invalid,
+}
+
+/// Per-link CSV decoding state
+#[derive(Debug)]
+pub struct CsvDecoder {
+    columns: Vec<Column>,
+    delimiter: char,
+    quote: Option<char>,
+    header: CsvHeader,
+    subject_column: Option<String>,
+    /// Column names from the header row of the current connection
+    header_names: Option<Vec<String>>,
+    /// Connection id and generation of the last line, to spot new connections
+    connection: Option<(u64, u64)>,
+}
+
+impl CsvDecoder {
+    /// Build the decoder from the link's `csv_*` options
+    pub fn from_config(config: &ConnectionConfig) -> anyhow::Result<Self> {
+        Ok(CsvDecoder {
+            columns: parse_columns(&config.csv_columns)?,
+            delimiter: parse_char(&config.csv_delimiter)
+                .with_context(|| format!("invalid csv_delimiter {:?}", config.csv_delimiter))?
+                .context("csv_delimiter must not be empty")?,
+            quote: parse_char(&config.csv_quote)
+                .with_context(|| format!("invalid csv_quote {:?}", config.csv_quote))?,
+            header: config.csv_header,
+            subject_column: (!config.csv_subject_column.is_empty())
+                .then(|| config.csv_subject_column.clone()),
+            header_names: None,
+            connection: None,
+        })
+    }
+
+    /// Decode a line received on the given connection id and generation
+    pub fn decode(&mut self, line: &str, connection: (u64, u64)) -> CsvLine {
+        let Some(fields) = split_record(line, self.delimiter, self.quote) else {
+            return CsvLine::Invalid;
+        };
+
+        let first = self.connection != Some(connection);
+        if first {
+            self.connection = Some(connection);
+            self.header_names = None;
+            if self.is_header(&fields) {
+                if self.columns.is_empty() {
+                    self.header_names = Some(fields);
+                }
+                return CsvLine::Header;
+            }
+        }
+
+        let mut record = Map::new();
+        for (i, field) in fields.into_iter().enumerate() {
+            let column = self.columns.get(i);
+            let name = match (column, &self.header_names) {
+                (Some(column), _) => column.name.clone(),
+                (None, Some(names)) if i < names.len() => names[i].clone(),
+                _ => i.to_string(),
+            };
+            let kind = column.map_or(ColumnType::String, |c| c.kind);
+            let Some(value) = typed_value(field, kind) else {
+                return CsvLine::Invalid;
+            };
+            record.insert(name, value);
+        }
+        let subject = self
+            .subject_column
+            .as_ref()
+            .and_then(|name| match record.get(name)? {
+                Value::Null => None,
+                Value::String(s) => Some(s.clone()),
+                v => Some(v.to_string()),
+            });
+        CsvLine::Record(record, subject)
+    }
+
+    /// Whether the first line of a connection is a header row. In auto mode
+    /// a header must repeat the configured column names or, without them,
+    /// consist of non-empty, non-numeric fields.
+    fn is_header(&self, fields: &[String]) -> bool {
+        match self.header {
+            CsvHeader::None => false,
+            CsvHeader::Always => true,
+            CsvHeader::Auto if self.columns.is_empty() => fields
+                .iter()
+                .all(|f| !f.is_empty() && f.parse::<f64>().is_err()),
+            CsvHeader::Auto => {
+                fields.len() == self.columns.len()
+                    && fields
+                        .iter()
+                        .zip(&self.columns)
+                        .all(|(f, c)| f.eq_ignore_ascii_case(&c.name))
+            }
+        }
+    }
+}
+
+/// Parse a `name[:type],...` column list
+fn parse_columns(spec: &str) -> anyhow::Result<Vec<Column>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|column| {
+            let (name, kind) = column.split_once(':').unwrap_or((column, "string"));
+            let kind = match kind.trim().to_lowercase().as_str() {
+                "string" | "str" => ColumnType::String,
+                "int" | "integer" => ColumnType::Int,
+                "float" | "number" => ColumnType::Float,
+                "bool" | "boolean" => ColumnType::Bool,
+                other => bail!("unknown type {other:?} for csv column {name:?}"),
+            };
+            Ok(Column {
+                name: name.trim().to_string(),
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// Parse a single-character option. `tab` and `\t` mean a tab; empty and
+/// `none` mean no character.
+fn parse_char(value: &str) -> anyhow::Result<Option<char>> {
+    match value {
+        "" | "none" => Ok(None),
+        "tab" | "\\t" => Ok(Some('\t')),
+        _ => {
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(Some(c)),
+                _ => bail!("expected a single character"),
+            }
+        }
+    }
+}
+
+/// Split a line into fields. Unquoted fields are trimmed; quoted fields keep
+/// their content verbatim, with a doubled quote standing for one. Returns
+/// `None` for an unterminated quote.
+fn split_record(line: &str, delimiter: char, quote: Option<char>) -> Option<Vec<String>> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if Some(c) == quote {
+                if chars.peek() == quote.as_ref() {
+                    field.push(c);
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if Some(c) == quote && !quoted && field.trim().is_empty() {
+            field.clear();
+            quoted = true;
+            in_quotes = true;
+        } else if c == delimiter {
+            fields.push(finish_field(std::mem::take(&mut field), quoted));
+            quoted = false;
+        } else if quoted && c.is_whitespace() {
+            // Padding after a closing quote
+        } else {
+            field.push(c);
+        }
+    }
+    if in_quotes {
+        return None;
+    }
+    fields.push(finish_field(field, quoted));
+    Some(fields)
+}
+
+fn finish_field(field: String, quoted: bool) -> String {
+    if quoted {
+        field
+    } else {
+        field.trim().to_string()
+    }
+}
+
+/// Convert a field according to its type hint; empty fields are `null`
+fn typed_value(field: String, kind: ColumnType) -> Option<Value> {
+    if field.is_empty() {
+        return Some(Value::Null);
+    }
+    match kind {
+        ColumnType::String => Some(field.into()),
+        ColumnType::Int => field.parse::<i64>().ok().map(Into::into),
+        ColumnType::Float => field.parse::<f64>().ok().map(Into::into),
+        ColumnType::Bool => match field.to_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Some(true.into()),
+            "false" | "0" | "no" | "off" => Some(false.into()),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn decoder(columns: &str, delimiter: &str, header: CsvHeader) -> CsvDecoder {
+        CsvDecoder::from_config(&ConnectionConfig {
+            csv_columns: columns.to_string(),
+            csv_delimiter: delimiter.to_string(),
+            csv_header: header,
+            csv_subject_column: "station".to_string(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn record(line: CsvLine) -> (Value, Option<String>) {
+        match line {
+            CsvLine::Record(record, subject) => (Value::Object(record), subject),
+            other => panic!("expected a record, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_split_record() {
+        assert_eq!(
+            split_record(r#"a, "b;""c"" ",,d"#, ',', Some('"')).unwrap(),
+            vec!["a", "b;\"c\" ", "", "d"]
+        );
+        assert_eq!(
+            split_record("x\ty\r\n", '\t', None).unwrap(),
+            vec!["x", "y"]
+        );
+        assert!(split_record(r#"a,"open"#, ',', Some('"')).is_none());
+    }
+
+    #[test]
+    fn test_typed_columns_and_subject() {
+        let mut csv = decoder("station,temp:float,count:int,ok:bool", ";", CsvHeader::None);
+        let (value, subject) = record(csv.decode("north; 21.5;7;yes;extra", (1, 1)));
+        assert_eq!(
+            value,
+            json!({"station": "north", "temp": 21.5, "count": 7, "ok": true, "4": "extra"})
+        );
+        assert_eq!(subject.as_deref(), Some("north"));
+
+        let (value, subject) = record(csv.decode(";;;", (1, 1)));
+        assert_eq!(value["temp"], Value::Null);
+        assert_eq!(subject, None);
+
+        assert_eq!(csv.decode("north;warm;7;yes", (1, 1)), CsvLine::Invalid);
+    }
+
+    #[test]
+    fn test_header_detection() {
+        let mut csv = decoder("", ",", CsvHeader::Auto);
+        assert_eq!(csv.decode("station,temp", (1, 1)), CsvLine::Header);
+        let (value, _) = record(csv.decode("south,3.5", (1, 1)));
+        assert_eq!(value, json!({"station": "south", "temp": "3.5"}));
+
+        // A new connection that starts with data keeps positional names
+        let (value, _) = record(csv.decode("east,4", (1, 2)));
+        assert_eq!(value, json!({"0": "east", "1": "4"}));
+
+        let mut csv = decoder("station,temp:float", ",", CsvHeader::Auto);
+        assert_eq!(csv.decode("Station,TEMP", (1, 1)), CsvLine::Header);
+        let (value, _) = record(csv.decode("west,1", (2, 1)));
+        assert_eq!(value, json!({"station": "west", "temp": 1.0}));
+
+        assert!(CsvDecoder::from_config(&ConnectionConfig {
+            csv_columns: "a:date".to_string(),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,373 @@
+//! Protocol decoders applied to frames before `parse_regex`.
+//!
+//! A decoder validates each frame, routes it to a protocol-specific subject and
+//! can rewrite its body as JSON or CBOR. Frames it rejects follow the link's
+//! [`InvalidPolicy`].
+
+use anyhow::{bail, Context as _};
+use serde::Serialize;
+
+use crate::config::{ConnectionConfig, DecoderKind, DecoderOutput, InvalidPolicy};
+use crate::csv::{CsvDecoder, CsvLine};
+use crate::jsonl::{self, Projection};
+use crate::nmea;
+use crate::stream::Frame;
+use crate::syslog;
+
+/// Decoder selected by a link's `decoder` option
+#[derive(Debug)]
+pub struct Decoder {
+    kind: DecoderKind,
+    output: DecoderOutput,
+    invalid: InvalidPolicy,
+    dead_letter_subject: String,
+    subject_pointer: Option<String>,
+    projection: Option<Projection>,
+    csv: Option<CsvDecoder>,
+    nmea_allow_missing_checksum: bool,
+}
+
+/// What a decoder did with a frame
+enum Outcome {
+    /// Forward the (possibly rewritten) frame
+    Accept,
+    /// Apply the invalid-frame policy
+    Reject,
+    /// Drop the frame silently, e.g. a consumed CSV header row
+    Skip,
+}
+
+impl From<bool> for Outcome {
+    fn from(accepted: bool) -> Self {
+        if accepted {
+            Outcome::Accept
+        } else {
+            Outcome::Reject
+        }
+    }
+}
+
+impl Decoder {
+    /// Build the decoder for a link, or `None` if `decoder` is unset
+    pub fn from_config(config: &ConnectionConfig) -> anyhow::Result<Option<Self>> {
+        if config.decoder == DecoderKind::None {
+            return Ok(None);
+        }
+        if !config.subject_pointer.is_empty() && !config.subject_pointer.starts_with('/') {
+            bail!(
+                "invalid subject_pointer {:?}: must start with `/`",
+                config.subject_pointer
+            );
+        }
+        let projection = if config.projection.is_empty() {
+            None
+        } else {
+            Some(config.projection.parse().context("invalid projection")?)
+        };
+        Ok(Some(Decoder {
+            kind: config.decoder,
+            output: config.decoder_output,
+            invalid: config.decoder_invalid,
+            dead_letter_subject: config.dead_letter_subject.clone(),
+            subject_pointer: (!config.subject_pointer.is_empty())
+                .then(|| config.subject_pointer.clone()),
+            projection,
+            csv: (config.decoder == DecoderKind::Csv)
+                .then(|| CsvDecoder::from_config(config))
+                .transpose()?,
+            nmea_allow_missing_checksum: config.nmea_allow_missing_checksum,
+        }))
+    }
+
+    /// Decode a frame, returning `None` if it should be dropped
+    pub fn decode(&mut self, mut frame: Frame) -> Option<Frame> {
+        let outcome = match self.kind {
+            DecoderKind::None => Outcome::Accept,
+            DecoderKind::Nmea => {
+                decode_nmea(&mut frame, self.output, self.nmea_allow_missing_checksum).into()
+            }
+            DecoderKind::Syslog => decode_syslog(&mut frame, self.output).into(),
+            DecoderKind::Jsonl => self.decode_jsonl(&mut frame).into(),
+            DecoderKind::Csv => self.decode_csv(&mut frame),
+        };
+        match outcome {
+            Outcome::Accept => Some(frame),
+            Outcome::Reject => self.reject(frame),
+            Outcome::Skip => None,
+        }
+    }
+
+    /// Split a CSV line into a record and route it by the subject column.
+    /// The body becomes the record, as JSON unless CBOR output is selected.
+    fn decode_csv(&mut self, frame: &mut Frame) -> Outcome {
+        let Some(csv) = self.csv.as_mut() else {
+            return Outcome::Reject;
+        };
+        let Ok(line) = std::str::from_utf8(&frame.data) else {
+            return Outcome::Reject;
+        };
+        let connection = (frame.meta.connection_id, frame.meta.generation);
+        let (record, subject) = match csv.decode(line, connection) {
+            CsvLine::Header => return Outcome::Skip,
+            CsvLine::Invalid => return Outcome::Reject,
+            CsvLine::Record(record, subject) => (record, subject),
+        };
+        let Some(data) = encode(&record, self.output) else {
+            return Outcome::Reject;
+        };
+        frame.data = data;
+        if subject.is_some() {
+            frame.subject = subject;
+        }
+        Outcome::Accept
+    }
+
+    /// Validate a JSON line, take its subject from `subject_pointer` and apply
+    /// the projection. Records without a value at the pointer keep the
+    /// default subject.
+    fn decode_jsonl(&self, frame: &mut Frame) -> bool {
+        let Ok(value) = serde_json::from_slice::<serde_json::Value>(&frame.data) else {
+            return false;
+        };
+        if let Some(subject) = self
+            .subject_pointer
+            .as_deref()
+            .and_then(|pointer| jsonl::subject_at(&value, pointer))
+        {
+            frame.subject = Some(subject);
+        }
+        let value = match &self.projection {
+            Some(projection) => projection.apply(&value),
+            None if self.output == DecoderOutput::Cbor => value,
+            None => return true,
+        };
+        match encode(&value, self.output) {
+            Some(data) => frame.data = data,
+            None => return false,
+        }
+        true
+    }
+
+    /// Apply the invalid-frame policy to a frame the decoder rejected
+    fn reject(&self, mut frame: Frame) -> Option<Frame> {
+        match self.invalid {
+            InvalidPolicy::Drop => None,
+            InvalidPolicy::Flag => {
+                frame.subject = Some(format!("{}.invalid", self.kind.as_str()));
+                Some(frame)
+            }
+            InvalidPolicy::DeadLetter => {
+                frame.subject = Some(self.dead_letter_subject.clone());
+                Some(frame)
+            }
+        }
+    }
+}
+
+/// Validate an NMEA sentence and route it to `nmea.<talker>.<type>`. Returns
+/// `false`, leaving the frame untouched, if the sentence is invalid.
+fn decode_nmea(frame: &mut Frame, output: DecoderOutput, allow_missing_checksum: bool) -> bool {
+    let Some(sentence) = std::str::from_utf8(&frame.data)
+        .ok()
+        .and_then(|line| nmea::parse_sentence(line, allow_missing_checksum))
+    else {
+        return false;
+    };
+    let subject = sentence.subject();
+    let data = match output {
+        DecoderOutput::Raw => None,
+        _ => match encode(&sentence.to_json(), output) {
+            Some(data) => Some(data),
+            None => return false,
+        },
+    };
+
+    frame.subject = Some(subject);
+    if let Some(data) = data {
+        frame.data = data;
+    }
+    true
+}
+
+/// Parse a syslog message and route it to `syslog.<facility>.<severity>`. The
+/// body becomes the message text, or the parsed fields with JSON output.
+fn decode_syslog(frame: &mut Frame, output: DecoderOutput) -> bool {
+    let Some(message) = std::str::from_utf8(&frame.data)
+        .ok()
+        .and_then(|line| syslog::parse_message(line.trim_end_matches(['\r', '\n'])))
+    else {
+        return false;
+    };
+    frame.subject = Some(message.subject());
+    frame.data = match output {
+        DecoderOutput::Raw => message.message.into_bytes(),
+        _ => match encode(&message, output) {
+            Some(data) => data,
+            None => return false,
+        },
+    };
+    true
+}
+
+/// Serialize decoded fields as CBOR for `cbor` output and as JSON otherwise
+fn encode<T: Serialize>(value: &T, output: DecoderOutput) -> Option<Vec<u8>> {
+    match output {
+        DecoderOutput::Cbor => {
+            let mut data = Vec::new();
+            ciborium::into_writer(value, &mut data).ok()?;
+            Some(data)
+        }
+        DecoderOutput::Raw | DecoderOutput::Json => serde_json::to_vec(value).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CsvHeader;
+
+    fn decoder(output: DecoderOutput, invalid: InvalidPolicy) -> Decoder {
+        Decoder::from_config(&ConnectionConfig {
+            decoder: DecoderKind::Nmea,
+            decoder_output: output,
+            decoder_invalid: invalid,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_nmea_decoder() {
+        let line = b"$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48".to_vec();
+        let frame = decoder(DecoderOutput::Raw, InvalidPolicy::Drop)
+            .decode(line.clone().into())
+            .unwrap();
+        assert_eq!(frame.subject.as_deref(), Some("nmea.GP.VTG"));
+        assert_eq!(frame.data, line);
+
+        let frame = decoder(DecoderOutput::Json, InvalidPolicy::Drop)
+            .decode(line.into())
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&frame.data).unwrap();
+        assert_eq!(json["speed_knots"], 5.5);
+    }
+
+    #[test]
+    fn test_syslog_decoder() {
+        let mut decoder = Decoder::from_config(&ConnectionConfig {
+            decoder: DecoderKind::Syslog,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        let frame = decoder
+            .decode(
+                b"<86>Feb  5 17:32:18 host sshd[42]: Accepted publickey\n"
+                    .to_vec()
+                    .into(),
+            )
+            .unwrap();
+        assert_eq!(frame.subject.as_deref(), Some("syslog.authpriv.info"));
+        assert_eq!(frame.data, b"Accepted publickey");
+
+        decoder.output = DecoderOutput::Json;
+        let frame = decoder
+            .decode(b"<86>1 - host sshd 42 - - Accepted".to_vec().into())
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&frame.data).unwrap();
+        assert_eq!(json["hostname"], "host");
+        assert_eq!(json["proc_id"], "42");
+        assert_eq!(json["facility"], "authpriv");
+        assert!(decoder.decode(b"not syslog".to_vec().into()).is_none());
+    }
+
+    #[test]
+    fn test_jsonl_decoder() {
+        let config = ConnectionConfig {
+            decoder: DecoderKind::Jsonl,
+            decoder_invalid: InvalidPolicy::DeadLetter,
+            subject_pointer: "/type".to_string(),
+            projection: "{id, lat: .pos.lat}".to_string(),
+            ..Default::default()
+        };
+        let mut decoder = Decoder::from_config(&config).unwrap().unwrap();
+        let frame = decoder
+            .decode(br#"{"type":"position","id":3,"pos":{"lat":48.1}}"#.to_vec().into())
+            .unwrap();
+        assert_eq!(frame.subject.as_deref(), Some("position"));
+        assert_eq!(frame.data, br#"{"id":3,"lat":48.1}"#);
+
+        let frame = decoder.decode(b"{\"id\":4}".to_vec().into()).unwrap();
+        assert_eq!(frame.subject, None);
+
+        let dead = decoder.decode(b"{truncated".to_vec().into()).unwrap();
+        assert_eq!(dead.subject.as_deref(), Some("stream.dead-letter"));
+        assert_eq!(dead.data, b"{truncated");
+
+        for (pointer, projection) in [("type", ""), ("", "{a b}")] {
+            assert!(Decoder::from_config(&ConnectionConfig {
+                subject_pointer: pointer.to_string(),
+                projection: projection.to_string(),
+                ..config.clone()
+            })
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_csv_decoder() {
+        let mut decoder = Decoder::from_config(&ConnectionConfig {
+            decoder: DecoderKind::Csv,
+            decoder_output: DecoderOutput::Json,
+            csv_columns: "station,temp:float".to_string(),
+            csv_header: CsvHeader::Always,
+            csv_subject_column: "station".to_string(),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        assert!(decoder.decode(b"station,temp".to_vec().into()).is_none());
+        let frame = decoder.decode(b"north,21.5\n".to_vec().into()).unwrap();
+        assert_eq!(frame.subject.as_deref(), Some("north"));
+        assert_eq!(frame.data, br#"{"station":"north","temp":21.5}"#);
+        assert!(decoder.decode(b"north,warm".to_vec().into()).is_none());
+
+        decoder.output = DecoderOutput::Cbor;
+        let frame = decoder.decode(b"south,3".to_vec().into()).unwrap();
+        let value: serde_json::Value = ciborium::from_reader(frame.data.as_slice()).unwrap();
+        assert_eq!(value, serde_json::json!({"station": "south", "temp": 3.0}));
+    }
+
+    #[test]
+    fn test_csv_decoder_defaults_to_json() {
+        let map = std::collections::HashMap::from([
+            ("decoder".to_string(), "csv".to_string()),
+            ("csv_columns".to_string(), "station,temp:float".to_string()),
+        ]);
+        let mut decoder = Decoder::from_config(&ConnectionConfig::from(&map))
+            .unwrap()
+            .unwrap();
+        let frame = decoder.decode(b"north,21.5\n".to_vec().into()).unwrap();
+        assert_eq!(frame.data, br#"{"station":"north","temp":21.5}"#);
+    }
+
+    #[test]
+    fn test_invalid_policies() {
+        let bad = || Frame::from(b"$GPVTG,054.7,T*00".to_vec());
+        assert!(decoder(DecoderOutput::Raw, InvalidPolicy::Drop)
+            .decode(bad())
+            .is_none());
+
+        let flagged = decoder(DecoderOutput::Json, InvalidPolicy::Flag)
+            .decode(bad())
+            .unwrap();
+        assert_eq!(flagged.subject.as_deref(), Some("nmea.invalid"));
+        assert_eq!(flagged.data, b"$GPVTG,054.7,T*00");
+
+        let dead = decoder(DecoderOutput::Raw, InvalidPolicy::DeadLetter)
+            .decode(bad())
+            .unwrap();
+        assert_eq!(dead.subject.as_deref(), Some("stream.dead-letter"));
+    }
+}
@@ -0,0 +1,184 @@
+//! Payload decompression for `decompress=gzip|zstd|lz4|deflate`.
+//!
+//! In `frame` mode each datagram or WebSocket message is decompressed on its
+//! own; in `stream` mode the whole TCP or Unix byte stream is decompressed
+//! before framing. Either way, output beyond `decompress_max_bytes` per frame
+//! is refused so a small compressed payload cannot expand without bound.
+//!
+//! `deflate` accepts both zlib-wrapped (RFC 1950, as sent by most libraries
+//! and HTTP) and raw (RFC 1951) deflate data, chosen by the zlib header.
+
+use std::io;
+
+use async_compression::tokio::bufread::{
+    DeflateDecoder, GzipDecoder, Lz4Decoder, ZlibDecoder, ZstdDecoder,
+};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt};
+
+use crate::config::Compression;
+
+/// Decompresses frames or streams with a configured codec and size limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decompressor {
+    codec: Compression,
+    /// Maximum decompressed size of a single frame
+    pub max_bytes: usize,
+}
+
+impl Decompressor {
+    /// Create a decompressor for `codec`, or `None` for [`Compression::None`]
+    pub fn new(codec: Compression, max_bytes: usize) -> Option<Self> {
+        (codec != Compression::None).then_some(Decompressor { codec, max_bytes })
+    }
+
+    /// Decompress one frame, failing if it is corrupt or would exceed the
+    /// size limit
+    pub async fn frame(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decoder(data, self.codec, is_zlib_header(data))
+            .take(self.max_bytes as u64 + 1)
+            .read_to_end(&mut out)
+            .await?;
+        if out.len() > self.max_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("decompressed frame exceeds {} bytes", self.max_bytes),
+            ));
+        }
+        Ok(out)
+    }
+
+    /// Wrap a compressed byte stream in a decoder. Concatenated gzip members
+    /// and zstd/lz4 frames are decoded one after another.
+    pub async fn stream<R>(&self, mut reader: R) -> io::Result<Box<dyn AsyncRead + Unpin + Send>>
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+    {
+        let zlib = self.codec == Compression::Deflate && is_zlib_header(reader.fill_buf().await?);
+        Ok(decoder(reader, self.codec, zlib))
+    }
+}
+
+fn decoder<'a, R>(
+    reader: R,
+    codec: Compression,
+    zlib: bool,
+) -> Box<dyn AsyncRead + Unpin + Send + 'a>
+where
+    R: AsyncBufRead + Unpin + Send + 'a,
+{
+    match codec {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => {
+            let mut decoder = GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        Compression::Zstd => {
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        Compression::Lz4 => {
+            let mut decoder = Lz4Decoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        Compression::Deflate if zlib => Box::new(ZlibDecoder::new(reader)),
+        Compression::Deflate => Box::new(DeflateDecoder::new(reader)),
+    }
+}
+
+/// Whether data starts with a zlib header: deflate method, a window of at
+/// most 32 KiB and a valid header checksum
+fn is_zlib_header(data: &[u8]) -> bool {
+    match data {
+        [cmf, flg, ..] => {
+            cmf & 0x0f == 8 && cmf >> 4 <= 7 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio::write::{
+        DeflateEncoder, GzipEncoder, Lz4Encoder, ZlibEncoder, ZstdEncoder,
+    };
+    use tokio::io::{AsyncWrite, AsyncWriteExt, BufReader};
+
+    use super::*;
+
+    async fn compress<W: AsyncWrite + Unpin>(mut encoder: W, data: &[u8]) -> W {
+        encoder.write_all(data).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        encoder
+    }
+
+    async fn compressed(codec: Compression, data: &[u8]) -> Vec<u8> {
+        match codec {
+            Compression::None => data.to_vec(),
+            Compression::Gzip => compress(GzipEncoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+            Compression::Zstd => compress(ZstdEncoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+            Compression::Lz4 => compress(Lz4Encoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+            Compression::Deflate => compress(ZlibEncoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frame_codecs() {
+        let data = b"$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+        for codec in [
+            Compression::Gzip,
+            Compression::Zstd,
+            Compression::Lz4,
+            Compression::Deflate,
+        ] {
+            let decompressor = Decompressor::new(codec, 1024).unwrap();
+            let frame = compressed(codec, data).await;
+            assert_eq!(decompressor.frame(&frame).await.unwrap(), data, "{codec:?}");
+            assert!(decompressor.frame(b"not compressed").await.is_err());
+        }
+
+        let raw = compress(DeflateEncoder::new(Vec::new()), data)
+            .await
+            .into_inner();
+        let deflate = Decompressor::new(Compression::Deflate, 1024).unwrap();
+        assert_eq!(deflate.frame(&raw).await.unwrap(), data);
+        assert!(Decompressor::new(Compression::None, 1024).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_frame_size_limit() {
+        let bomb = compressed(Compression::Gzip, &[b'a'; 1 << 20]).await;
+        assert!(bomb.len() < 4096);
+        let err = Decompressor::new(Compression::Gzip, 64 * 1024)
+            .unwrap()
+            .frame(&bomb)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_stream_concatenated_members() {
+        let mut data = compressed(Compression::Gzip, b"one\ntw").await;
+        data.extend(compressed(Compression::Gzip, b"o\nthree\n").await);
+        let mut reader = Decompressor::new(Compression::Gzip, 1024)
+            .unwrap()
+            .stream(BufReader::new(std::io::Cursor::new(data)))
+            .await
+            .unwrap();
+        let mut out = String::new();
+        reader.read_to_string(&mut out).await.unwrap();
+        assert_eq!(out, "one\ntwo\nthree\n");
+    }
+}
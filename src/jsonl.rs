@@ -0,0 +1,243 @@
+//! JSON Lines helpers: subject selection by JSON pointer and jq-like projections.
+//!
+//! A projection is either a path such as `.data.position[0]` or an object
+//! constructor such as `{type, lat: .data.lat, "id": .meta["device-id"]}`,
+//! where a bare `name` is shorthand for `name: .name`. Missing values project
+//! to `null`, as in jq.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Context as _};
+use serde_json::Value;
+
+/// One step of a projection path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// Object member
+    Key(String),
+    /// Array element
+    Index(usize),
+}
+
+/// A parsed `projection` expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection {
+    /// Select the value at a path
+    Path(Vec<Segment>),
+    /// Build an object from named paths
+    Object(Vec<(String, Vec<Segment>)>),
+}
+
+impl Projection {
+    /// Apply the projection to a value
+    pub fn apply(&self, value: &Value) -> Value {
+        match self {
+            Projection::Path(path) => select(value, path),
+            Projection::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, path)| (name.clone(), select(value, path)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl FromStr for Projection {
+    type Err = anyhow::Error;
+
+    fn from_str(expr: &str) -> anyhow::Result<Self> {
+        let mut parser = Parser { rest: expr.trim() };
+        let projection = if parser.eat('{') {
+            let mut fields = Vec::new();
+            while !parser.eat('}') {
+                if !fields.is_empty() && !parser.eat(',') {
+                    bail!("expected `,` or `}}` in projection {expr:?}");
+                }
+                let name = if parser.peek() == Some('"') {
+                    parser.string()?
+                } else {
+                    parser.ident()?
+                };
+                let path = if parser.eat(':') {
+                    parser.path()?
+                } else {
+                    vec![Segment::Key(name.clone())]
+                };
+                fields.push((name, path));
+            }
+            Projection::Object(fields)
+        } else {
+            Projection::Path(parser.path()?)
+        };
+        if !parser.rest.is_empty() {
+            bail!("unexpected {:?} in projection {expr:?}", parser.rest);
+        }
+        Ok(projection)
+    }
+}
+
+/// Read the value at `pointer` as a subject. Strings are used as-is, numbers
+/// and booleans are formatted; other values select no subject.
+pub fn subject_at(value: &Value, pointer: &str) -> Option<String> {
+    match value.pointer(pointer)? {
+        Value::String(s) => Some(s.clone()),
+        v @ (Value::Number(_) | Value::Bool(_)) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+fn select(mut value: &Value, path: &[Segment]) -> Value {
+    for segment in path {
+        let next = match segment {
+            Segment::Key(key) => value.get(key),
+            Segment::Index(index) => value.get(index),
+        };
+        match next {
+            Some(next) => value = next,
+            None => return Value::Null,
+        }
+    }
+    value.clone()
+}
+
+/// Cursor over a projection expression, skipping whitespace between tokens
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.rest = self.rest.trim_start();
+        self.rest.chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.rest = &self.rest[c.len_utf8()..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> anyhow::Result<String> {
+        self.peek();
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest.len());
+        if end == 0 || self.rest.starts_with(|c: char| c.is_ascii_digit()) {
+            bail!("expected a name at {:?}", self.rest);
+        }
+        let (ident, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(ident.to_string())
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        self.peek();
+        let mut de = serde_json::Deserializer::from_str(self.rest).into_iter::<String>();
+        let value = de
+            .next()
+            .context("expected a string")?
+            .context("invalid string")?;
+        self.rest = &self.rest[de.byte_offset()..];
+        Ok(value)
+    }
+
+    /// Parse `.`, `.a.b`, `.a[0]` or `.["key"]`
+    fn path(&mut self) -> anyhow::Result<Vec<Segment>> {
+        if !self.eat('.') {
+            bail!("expected a path starting with `.` at {:?}", self.rest);
+        }
+        let mut path = Vec::new();
+        if matches!(self.rest.chars().next(), Some(c) if c.is_ascii_alphabetic() || c == '_') {
+            path.push(Segment::Key(self.ident()?));
+        }
+        loop {
+            if self.rest.starts_with('.') {
+                self.rest = &self.rest[1..];
+                let key = if self.rest.starts_with('"') {
+                    self.string()?
+                } else {
+                    self.ident()?
+                };
+                path.push(Segment::Key(key));
+            } else if self.rest.starts_with('[') {
+                self.rest = &self.rest[1..];
+                if self.peek() == Some('"') {
+                    path.push(Segment::Key(self.string()?));
+                } else {
+                    self.peek();
+                    let end = self
+                        .rest
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(self.rest.len());
+                    let index = self.rest[..end]
+                        .parse()
+                        .with_context(|| format!("expected an index at {:?}", self.rest))?;
+                    self.rest = &self.rest[end..];
+                    path.push(Segment::Index(index));
+                }
+                if !self.eat(']') {
+                    bail!("expected `]` at {:?}", self.rest);
+                }
+            } else {
+                return Ok(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn record() -> Value {
+        json!({
+            "type": "position",
+            "seq": 7,
+            "data": {"lat": 48.1, "lon": 11.5, "sats": [3, 9]},
+            "meta": {"device-id": "gps-1"},
+        })
+    }
+
+    #[test]
+    fn test_path_projection() {
+        let value = record();
+        let project = |expr: &str| expr.parse::<Projection>().unwrap().apply(&value);
+        assert_eq!(project("."), value);
+        assert_eq!(project(".data.lat"), json!(48.1));
+        assert_eq!(project(".data.sats[1]"), json!(9));
+        assert_eq!(project(".meta[\"device-id\"]"), json!("gps-1"));
+        assert_eq!(project(".missing.deeper"), Value::Null);
+    }
+
+    #[test]
+    fn test_object_projection() {
+        let projection: Projection = "{type, lat: .data.lat, \"device\": .meta.\"device-id\"}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            projection.apply(&record()),
+            json!({"type": "position", "lat": 48.1, "device": "gps-1"})
+        );
+
+        assert!("{type lat}".parse::<Projection>().is_err());
+        assert!("data".parse::<Projection>().is_err());
+        assert!(".data[x]".parse::<Projection>().is_err());
+        assert!(".data junk".parse::<Projection>().is_err());
+    }
+
+    #[test]
+    fn test_subject_at() {
+        let value = record();
+        assert_eq!(subject_at(&value, "/type").as_deref(), Some("position"));
+        assert_eq!(subject_at(&value, "/seq").as_deref(), Some("7"));
+        assert_eq!(subject_at(&value, "/data"), None);
+        assert_eq!(subject_at(&value, "/nope"), None);
+    }
+}
@@ -4,9 +4,27 @@
 //! to wasmCloud components via wRPC. It implements unidirectional communication
 //! (receiving only) with per-component stream management.
 
+mod batch;
+mod capture;
+mod charset;
 mod config;
+mod csv;
+mod decode;
+mod decompress;
+mod jsonl;
+mod metadata;
+mod nmea;
+mod parse;
 mod provider;
+mod proxy;
+mod ratelimit;
+mod spool;
+mod sse;
+mod stats;
 mod stream;
+mod syslog;
+mod tail;
+mod telnet;
 
 use provider::TcpUdpStreamProvider;
 
@@ -0,0 +1,244 @@
+//! Encodings that carry per-frame receive metadata to components.
+//!
+//! `wasmcloud:messaging@0.2.0` messages only have a subject, body and reply-to,
+//! so metadata travels either as `;key=value` pairs appended to the subject or
+//! inside a JSON, CBOR or MessagePack envelope that wraps the body.
+//! `wasmcloud:messaging@0.3.0` messages carry it as headers.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+use crate::config::EnvelopeFormat;
+use crate::stream::{monotonic_origin, Frame, FrameMetadata};
+
+/// Envelope wrapping a frame and its receive metadata.
+///
+/// The payload is a base64 string in JSON and a native byte string in CBOR
+/// and MessagePack.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Envelope<P> {
+    /// Frame payload
+    pub payload: P,
+    /// Sender address, see [`FrameMetadata::peer`]
+    pub peer: Option<String>,
+    /// Local address the frame was received on
+    pub local: Option<String>,
+    /// Protocol the frame was received over
+    pub protocol: String,
+    /// Receive time in milliseconds since the Unix epoch
+    pub received_at_ms: Option<u64>,
+    /// Monotonic receive time in nanoseconds since the provider's first frame
+    pub received_mono_ns: Option<u64>,
+    /// Sequence number of the frame within its connection generation
+    pub seq: u64,
+    /// Process-unique id of the stream client
+    pub connection_id: u64,
+    /// Connection generation within the stream client
+    pub generation: u64,
+}
+
+impl<P> Envelope<P> {
+    /// Build an envelope from a received frame and its encoded payload
+    pub fn new(frame: &Frame, payload: P) -> Self {
+        let meta = &frame.meta;
+        Envelope {
+            payload,
+            peer: meta.peer.clone(),
+            local: meta.local.clone(),
+            protocol: meta.protocol.to_string(),
+            received_at_ms: meta.received_at.map(unix_millis),
+            received_mono_ns: meta
+                .received_mono
+                .map(|t| t.saturating_duration_since(monotonic_origin()).as_nanos() as u64),
+            seq: meta.seq,
+            connection_id: meta.connection_id,
+            generation: meta.generation,
+        }
+    }
+}
+
+/// Encode a frame as an envelope body in the given format. [`EnvelopeFormat::None`]
+/// falls back to JSON, since the caller has already chosen to wrap the frame.
+pub fn encode_envelope(frame: &Frame, format: EnvelopeFormat) -> anyhow::Result<Vec<u8>> {
+    match format {
+        EnvelopeFormat::None | EnvelopeFormat::Json => serialize(&json_envelope(frame), format),
+        EnvelopeFormat::Cbor | EnvelopeFormat::Msgpack => {
+            serialize(&binary_envelope(frame), format)
+        }
+    }
+}
+
+/// Encode several frames as an array of envelopes in the given format
+pub fn encode_envelope_array(frames: &[Frame], format: EnvelopeFormat) -> anyhow::Result<Vec<u8>> {
+    match format {
+        EnvelopeFormat::None | EnvelopeFormat::Json => serialize(
+            &frames.iter().map(json_envelope).collect::<Vec<_>>(),
+            format,
+        ),
+        EnvelopeFormat::Cbor | EnvelopeFormat::Msgpack => serialize(
+            &frames.iter().map(binary_envelope).collect::<Vec<_>>(),
+            format,
+        ),
+    }
+}
+
+fn json_envelope(frame: &Frame) -> Envelope<String> {
+    Envelope::new(
+        frame,
+        base64::engine::general_purpose::STANDARD.encode(&frame.data),
+    )
+}
+
+fn binary_envelope(frame: &Frame) -> Envelope<&serde_bytes::Bytes> {
+    Envelope::new(frame, serde_bytes::Bytes::new(&frame.data))
+}
+
+fn serialize<T: Serialize>(value: &T, format: EnvelopeFormat) -> anyhow::Result<Vec<u8>> {
+    match format {
+        EnvelopeFormat::None | EnvelopeFormat::Json => Ok(serde_json::to_vec(value)?),
+        EnvelopeFormat::Cbor => {
+            let mut out = Vec::new();
+            ciborium::into_writer(value, &mut out)?;
+            Ok(out)
+        }
+        EnvelopeFormat::Msgpack => Ok(rmp_serde::to_vec_named(value)?),
+    }
+}
+
+/// Append metadata to a subject as `;peer=..;ts=..;seq=..;conn=..`, omitting
+/// values that are not known
+pub fn subject_with_metadata(subject: &str, meta: &FrameMetadata) -> String {
+    let mut out = subject.to_string();
+    if let Some(peer) = &meta.peer {
+        out.push_str(&format!(";peer={peer}"));
+    }
+    if let Some(received_at) = meta.received_at {
+        out.push_str(&format!(";ts={}", unix_millis(received_at)));
+    }
+    out.push_str(&format!(";seq={};conn={}", meta.seq, meta.connection_id));
+    out
+}
+
+/// Headers of a `wasmcloud:messaging@0.3.0` message: the subject, which a
+/// 0.3.0 message cannot otherwise carry, then the receive metadata under the
+/// names of the [`Envelope`] fields, omitting values that are not known
+pub fn headers(subject: &str, meta: &FrameMetadata) -> Vec<(String, String)> {
+    let mut headers = vec![("subject".to_string(), subject.to_string())];
+    if let Some(peer) = &meta.peer {
+        headers.push(("peer".to_string(), peer.clone()));
+    }
+    if let Some(local) = &meta.local {
+        headers.push(("local".to_string(), local.clone()));
+    }
+    headers.push(("protocol".to_string(), meta.protocol.to_string()));
+    if let Some(received_at) = meta.received_at {
+        headers.push((
+            "received_at_ms".to_string(),
+            unix_millis(received_at).to_string(),
+        ));
+    }
+    if let Some(received_mono) = meta.received_mono {
+        let mono_ns = received_mono.saturating_duration_since(monotonic_origin());
+        headers.push((
+            "received_mono_ns".to_string(),
+            mono_ns.as_nanos().to_string(),
+        ));
+    }
+    headers.push(("seq".to_string(), meta.seq.to_string()));
+    headers.push(("connection_id".to_string(), meta.connection_id.to_string()));
+    headers.push(("generation".to_string(), meta.generation.to_string()));
+    headers
+}
+
+/// Milliseconds since the Unix epoch, saturating at zero for earlier times
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn frame() -> Frame {
+        Frame {
+            data: b"$GPGGA,1".to_vec(),
+            subject: None,
+            meta: FrameMetadata {
+                peer: Some("10.0.0.5:4001".to_string()),
+                local: Some("0.0.0.0:10110".to_string()),
+                protocol: "udp",
+                received_at: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+                received_mono: Some(monotonic_origin() + Duration::from_nanos(500)),
+                seq: 42,
+                connection_id: 3,
+                generation: 2,
+            },
+        }
+    }
+
+    #[test]
+    fn test_subject_with_metadata() {
+        assert_eq!(
+            subject_with_metadata("stream.127.0.0.1:9000", &frame().meta),
+            "stream.127.0.0.1:9000;peer=10.0.0.5:4001;ts=1700000000123;seq=42;conn=3"
+        );
+    }
+
+    #[test]
+    fn test_headers() {
+        let headers = headers("stream.127.0.0.1:9000", &frame().meta);
+        let value = |key: &str| {
+            headers
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(value("subject"), Some("stream.127.0.0.1:9000"));
+        assert_eq!(value("peer"), Some("10.0.0.5:4001"));
+        assert_eq!(value("received_at_ms"), Some("1700000000123"));
+        assert_eq!(value("received_mono_ns"), Some("500"));
+        assert_eq!(value("seq"), Some("42"));
+        assert_eq!(value("generation"), Some("2"));
+
+        assert_eq!(value("local"), Some("0.0.0.0:10110"));
+
+        let sparse = super::headers("s", &FrameMetadata::default());
+        assert!(!sparse
+            .iter()
+            .any(|(k, _)| k == "peer" || k == "received_at_ms"));
+    }
+
+    #[test]
+    fn test_envelope_json() {
+        let body = encode_envelope(&frame(), EnvelopeFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["payload"], "JEdQR0dBLDE=");
+        assert_eq!(json["peer"], "10.0.0.5:4001");
+        assert_eq!(json["local"], "0.0.0.0:10110");
+        assert_eq!(json["protocol"], "udp");
+        assert_eq!(json["received_at_ms"], 1_700_000_000_123u64);
+        assert_eq!(json["received_mono_ns"], 500);
+        assert_eq!(json["seq"], 42);
+        assert_eq!(json["connection_id"], 3);
+        assert_eq!(json["generation"], 2);
+    }
+
+    #[test]
+    fn test_envelope_binary_formats() {
+        let body = encode_envelope(&frame(), EnvelopeFormat::Cbor).unwrap();
+        let cbor: Envelope<serde_bytes::ByteBuf> = ciborium::from_reader(&body[..]).unwrap();
+        assert_eq!(cbor.payload.as_ref(), b"$GPGGA,1");
+        assert_eq!(cbor.seq, 42);
+
+        let body = encode_envelope(&frame(), EnvelopeFormat::Msgpack).unwrap();
+        let msgpack: Envelope<serde_bytes::ByteBuf> = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(msgpack.payload.as_ref(), b"$GPGGA,1");
+        assert_eq!(msgpack.protocol, "udp");
+    }
+}
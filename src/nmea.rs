@@ -0,0 +1,223 @@
+//! NMEA 0183 sentence decoding for GPS and AIS feeds.
+//!
+//! Sentences look like `$GPGGA,...*47` or `!AIVDM,...*5C`: a start character,
+//! a talker id and sentence type, comma-separated fields and a `*XX`
+//! checksum, the XOR of every byte between the start character and `*`.
+
+use serde_json::{json, Value};
+
+/// A sentence that passed checksum validation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sentence<'a> {
+    /// Talker id, e.g. `GP` or `AI`; `P` for proprietary sentences
+    pub talker: &'a str,
+    /// Sentence type, e.g. `GGA` or `VDM`
+    pub kind: &'a str,
+    /// Fields following the address field
+    pub fields: Vec<&'a str>,
+}
+
+impl Sentence<'_> {
+    /// Subject for the sentence, `nmea.<talker>.<type>`
+    pub fn subject(&self) -> String {
+        format!("nmea.{}.{}", self.talker, self.kind)
+    }
+
+    /// Parsed fields as JSON. GGA, RMC and VTG are decoded, AIS VDM/VDO
+    /// sentences pass their armoured payload through, and other sentences
+    /// carry their raw fields.
+    pub fn to_json(&self) -> Value {
+        let mut value = match self.kind {
+            "GGA" => json!({
+                "time": self.text(0),
+                "lat": coordinate(self.field(1), self.field(2)),
+                "lon": coordinate(self.field(3), self.field(4)),
+                "fix_quality": self.number::<u64>(5),
+                "satellites": self.number::<u64>(6),
+                "hdop": self.number::<f64>(7),
+                "altitude_m": self.number::<f64>(8),
+                "geoid_separation_m": self.number::<f64>(10),
+            }),
+            "RMC" => json!({
+                "time": self.text(0),
+                "valid": self.field(1) == "A",
+                "lat": coordinate(self.field(2), self.field(3)),
+                "lon": coordinate(self.field(4), self.field(5)),
+                "speed_knots": self.number::<f64>(6),
+                "course_deg": self.number::<f64>(7),
+                "date": self.text(8),
+                "magnetic_variation_deg": coordinate_sign(self.number::<f64>(9), self.field(10)),
+            }),
+            "VTG" => json!({
+                "course_true_deg": self.number::<f64>(0),
+                "course_magnetic_deg": self.number::<f64>(2),
+                "speed_knots": self.number::<f64>(4),
+                "speed_kmh": self.number::<f64>(6),
+            }),
+            "VDM" | "VDO" => json!({
+                "fragments": self.number::<u64>(0),
+                "fragment": self.number::<u64>(1),
+                "message_id": self.text(2),
+                "channel": self.text(3),
+                "payload": self.text(4),
+                "fill_bits": self.number::<u64>(5),
+            }),
+            _ => json!({ "fields": self.fields }),
+        };
+        value["talker"] = self.talker.into();
+        value["type"] = self.kind.into();
+        value
+    }
+
+    fn field(&self, index: usize) -> &str {
+        self.fields.get(index).copied().unwrap_or_default()
+    }
+
+    fn text(&self, index: usize) -> Value {
+        match self.field(index) {
+            "" => Value::Null,
+            text => text.into(),
+        }
+    }
+
+    fn number<T: std::str::FromStr + Into<Value>>(&self, index: usize) -> Value {
+        self.field(index)
+            .parse::<T>()
+            .map_or(Value::Null, Into::into)
+    }
+}
+
+/// Parse a sentence, validating its checksum. Returns `None` for malformed
+/// sentences, checksum mismatches and, unless `allow_missing_checksum` is set,
+/// sentences without a checksum.
+pub fn parse_sentence(line: &str, allow_missing_checksum: bool) -> Option<Sentence<'_>> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let body = line.strip_prefix(['$', '!'])?;
+    let body = match body.rsplit_once('*') {
+        Some((body, checksum)) => {
+            if checksum.len() != 2 || !checksum.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let expected = u8::from_str_radix(checksum, 16).ok()?;
+            if body.bytes().fold(0, |acc, b| acc ^ b) != expected {
+                return None;
+            }
+            body
+        }
+        None if allow_missing_checksum => body,
+        None => return None,
+    };
+
+    let mut fields = body.split(',');
+    let address = fields.next()?;
+    if !address.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let (talker, kind) = if address.starts_with('P') {
+        address.split_at(1)
+    } else if address.len() >= 3 {
+        address.split_at(2)
+    } else {
+        return None;
+    };
+    Some(Sentence {
+        talker,
+        kind,
+        fields: fields.collect(),
+    })
+}
+
+/// Convert an NMEA `ddmm.mmmm` / `dddmm.mmmm` value and hemisphere to signed
+/// decimal degrees
+fn coordinate(value: &str, hemisphere: &str) -> Value {
+    if !value.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return Value::Null;
+    }
+    let dot = value.find('.').unwrap_or(value.len());
+    if dot < 2 {
+        return Value::Null;
+    }
+    let (degrees, minutes) = value.split_at(dot - 2);
+    match (degrees.parse::<f64>(), minutes.parse::<f64>()) {
+        (Ok(degrees), Ok(minutes)) => {
+            coordinate_sign((degrees + minutes / 60.0).into(), hemisphere)
+        }
+        _ => Value::Null,
+    }
+}
+
+/// Negate a value for the southern and western hemispheres
+fn coordinate_sign(value: Value, hemisphere: &str) -> Value {
+    match (value.as_f64(), hemisphere) {
+        (Some(v), "S" | "W") => (-v).into(),
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGA: &str = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+
+    #[test]
+    fn test_parse_sentence_checksum() {
+        let sentence = parse_sentence(GGA, false).unwrap();
+        assert_eq!(sentence.subject(), "nmea.GP.GGA");
+        assert_eq!(sentence.fields[0], "123519");
+
+        assert!(parse_sentence(&GGA.replace("*47", "*48"), false).is_none());
+        assert!(parse_sentence(&GGA.replace("*47", "*4"), false).is_none());
+        assert!(parse_sentence("GPGGA,123519", true).is_none());
+
+        assert!(parse_sentence("$PGRMZ,246,f,3", false).is_none());
+        assert_eq!(
+            parse_sentence("$PGRMZ,246,f,3", true).unwrap().subject(),
+            "nmea.P.GRMZ"
+        );
+    }
+
+    #[test]
+    fn test_gga_rmc_vtg_json() {
+        let gga = parse_sentence(GGA, false).unwrap().to_json();
+        assert_eq!(gga["type"], "GGA");
+        assert!((gga["lat"].as_f64().unwrap() - 48.1173).abs() < 1e-4);
+        assert!((gga["lon"].as_f64().unwrap() - 11.516_666).abs() < 1e-4);
+        assert_eq!(gga["satellites"], 8);
+        assert_eq!(gga["altitude_m"], 545.4);
+
+        let rmc = parse_sentence(
+            "$GPRMC,123519,A,4807.038,S,01131.000,W,022.4,084.4,230394,003.1,W*65",
+            false,
+        )
+        .unwrap()
+        .to_json();
+        assert_eq!(rmc["valid"], true);
+        assert!(rmc["lat"].as_f64().unwrap() < 0.0);
+        assert!(rmc["lon"].as_f64().unwrap() < 0.0);
+        assert_eq!(rmc["speed_knots"], 22.4);
+        assert_eq!(rmc["magnetic_variation_deg"], -3.1);
+
+        // Non-ASCII input must not split inside a character
+        assert_eq!(coordinate("€.0", "N"), Value::Null);
+        assert_eq!(coordinate("48€7.038", "N"), Value::Null);
+        assert_eq!(coordinate("-807.038", "N"), Value::Null);
+
+        let vtg = parse_sentence("$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48", false)
+            .unwrap()
+            .to_json();
+        assert_eq!(vtg["course_true_deg"], 54.7);
+        assert_eq!(vtg["speed_kmh"], 10.2);
+    }
+
+    #[test]
+    fn test_aivdm_passthrough() {
+        let ais = parse_sentence("!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*5C", false).unwrap();
+        assert_eq!(ais.subject(), "nmea.AI.VDM");
+        let json = ais.to_json();
+        assert_eq!(json["payload"], "177KQJ5000G?tO`K>RA1wUbN0TKH");
+        assert_eq!(json["message_id"], Value::Null);
+        assert_eq!(json["channel"], "B");
+        assert_eq!(json["fill_bits"], 0);
+    }
+}
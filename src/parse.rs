@@ -0,0 +1,175 @@
+//! Regex-based parsing of text frames.
+//!
+//! A link's `parse_regex` is matched against each frame after it is read from
+//! the transport. Named captures fill `subject_template` and can replace the
+//! body with a JSON object of the captured fields; frames that do not match
+//! follow the link's [`NoMatchPolicy`].
+
+use anyhow::Context as _;
+use regex::bytes::{Captures, Regex};
+
+use crate::config::{ConnectionConfig, NoMatchPolicy, ParseBody};
+use crate::stream::Frame;
+
+/// Compiled `parse_regex` and what to do with its matches
+#[derive(Debug)]
+pub struct RegexParser {
+    regex: Regex,
+    subject_template: Option<String>,
+    body: ParseBody,
+    no_match: NoMatchPolicy,
+    dead_letter_subject: String,
+}
+
+impl RegexParser {
+    /// Build the parser for a link, or `None` if `parse_regex` is unset
+    pub fn from_config(config: &ConnectionConfig) -> anyhow::Result<Option<Self>> {
+        if config.parse_regex.is_empty() {
+            return Ok(None);
+        }
+        let regex = Regex::new(&config.parse_regex)
+            .with_context(|| format!("invalid parse_regex {:?}", config.parse_regex))?;
+        Ok(Some(RegexParser {
+            regex,
+            subject_template: (!config.subject_template.is_empty())
+                .then(|| config.subject_template.clone()),
+            body: config.parse_body,
+            no_match: config.parse_no_match,
+            dead_letter_subject: config.dead_letter_subject.clone(),
+        }))
+    }
+
+    /// Parse a frame, returning `None` if it should be dropped
+    pub fn apply(&self, mut frame: Frame) -> Option<Frame> {
+        let Some(captures) = self.regex.captures(&frame.data) else {
+            return match self.no_match {
+                NoMatchPolicy::Forward => Some(frame),
+                NoMatchPolicy::Drop => None,
+                NoMatchPolicy::DeadLetter => {
+                    frame.subject = Some(self.dead_letter_subject.clone());
+                    Some(frame)
+                }
+            };
+        };
+
+        let subject = self
+            .subject_template
+            .as_deref()
+            .map(|template| render_template(template, &captures));
+        if self.body == ParseBody::Json {
+            let fields: serde_json::Map<String, serde_json::Value> = self
+                .regex
+                .capture_names()
+                .flatten()
+                .map(|name| {
+                    let value = captures.name(name).map_or(serde_json::Value::Null, |m| {
+                        String::from_utf8_lossy(m.as_bytes()).into()
+                    });
+                    (name.to_string(), value)
+                })
+                .collect();
+            frame.data = serde_json::Value::Object(fields).to_string().into_bytes();
+        }
+        if subject.is_some() {
+            frame.subject = subject;
+        }
+        Some(frame)
+    }
+}
+
+/// Replace each `{name}` (or `{index}`) in `template` with the matching capture.
+/// Captures that did not participate in the match render as empty strings and
+/// an unclosed `{` is kept literally.
+fn render_template(template: &str, captures: &Captures) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+        let name = &after[..end];
+        let value = captures
+            .name(name)
+            .or_else(|| name.parse().ok().and_then(|i| captures.get(i)));
+        if let Some(value) = value {
+            out.push_str(&String::from_utf8_lossy(value.as_bytes()));
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parser(no_match: NoMatchPolicy, body: ParseBody) -> RegexParser {
+        RegexParser::from_config(&ConnectionConfig {
+            parse_regex: r"^(?P<id>[a-z0-9]+) temp=(?P<temp>[\d.]+)(?: unit=(?P<unit>\w))?$"
+                .to_string(),
+            subject_template: "sensor.{id}".to_string(),
+            parse_body: body,
+            parse_no_match: no_match,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_subject_template_and_json_body() {
+        let frame = parser(NoMatchPolicy::Forward, ParseBody::Json)
+            .apply(b"s17 temp=21.5".to_vec().into())
+            .unwrap();
+        assert_eq!(frame.subject.as_deref(), Some("sensor.s17"));
+        let json: serde_json::Value = serde_json::from_slice(&frame.data).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"id": "s17", "temp": "21.5", "unit": null})
+        );
+
+        let frame = parser(NoMatchPolicy::Forward, ParseBody::Raw)
+            .apply(b"s17 temp=21.5 unit=C".to_vec().into())
+            .unwrap();
+        assert_eq!(frame.data, b"s17 temp=21.5 unit=C");
+    }
+
+    #[test]
+    fn test_no_match_policies() {
+        let frame = || Frame::from(b"garbage".to_vec());
+        let forwarded = parser(NoMatchPolicy::Forward, ParseBody::Json)
+            .apply(frame())
+            .unwrap();
+        assert_eq!(forwarded.subject, None);
+        assert_eq!(forwarded.data, b"garbage");
+
+        assert!(parser(NoMatchPolicy::Drop, ParseBody::Json)
+            .apply(frame())
+            .is_none());
+
+        let dead = parser(NoMatchPolicy::DeadLetter, ParseBody::Json)
+            .apply(frame())
+            .unwrap();
+        assert_eq!(dead.subject.as_deref(), Some("stream.dead-letter"));
+        assert_eq!(dead.data, b"garbage");
+    }
+
+    #[test]
+    fn test_render_template() {
+        let regex = Regex::new(r"(?P<a>x)(y)?").unwrap();
+        let captures = regex.captures(b"x").unwrap();
+        assert_eq!(
+            render_template("{a}.{1}.{2}.{missing}.{", &captures),
+            "x.x...{"
+        );
+        assert!(RegexParser::from_config(&ConnectionConfig {
+            parse_regex: "(".to_string(),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -1,37 +1,292 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use anyhow::Context as _;
 use bytes::Bytes;
-use tokio::sync::RwLock;
+use serde::Serialize;
+use tokio::sync::{mpsc, Notify, OnceCell, RwLock};
+use tokio::time::Instant;
 use tracing::{error, info, warn};
 use wasmcloud_provider_sdk::initialize_observability;
+use wasmcloud_provider_sdk::provider::WrpcClient;
 use wasmcloud_provider_sdk::{
     get_connection, run_provider, serve_provider_exports, Context as SdkContext,
     LinkConfig as SdkLinkConfig, LinkDeleteInfo, Provider, ProviderInitConfig,
 };
+use wit_bindgen_wrpc::wrpc_transport::{Invoke, ResourceBorrow, ResourceOwn};
 
-use crate::config::{ConnectionConfig, ProviderConfig};
-use crate::stream::StreamClient;
+use crate::batch::{encode_batch, Batcher};
+use crate::config::{BatchFormat, ConnectionConfig, EnvelopeFormat, MetadataMode, ProviderConfig};
+use crate::decode::Decoder;
+use crate::metadata::{self, encode_envelope, subject_with_metadata};
+use crate::parse::RegexParser;
+use crate::spool::{Spool, SpoolStatus};
+use crate::stats::{LinkStats, LinkStatsSnapshot};
+use crate::stream::{Frame, FrameMetadata, FrameSink, StreamClient};
+
+/// Subject a linked component can `request` to receive its link's counters
+/// as JSON
+const STATUS_SUBJECT: &str = "tcp-udp-stream.status";
+
+/// Delay before retrying delivery of spooled messages after a failure
+const SPOOL_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Frames a link may queue for its delivery task before the reader waits
+const DELIVERY_QUEUE_LEN: usize = 1024;
 
 pub(crate) mod bindings {
     wit_bindgen_wrpc::generate!({ generate_all });
 }
 
 // Import the standard messaging interfaces from WIT
-use bindings::wasmcloud::messaging::handler;
-use bindings::wasmcloud::messaging::types;
+use bindings::exports::wasmcloud::messaging0_2_0::consumer;
+use bindings::exports::wasmcloud::messaging0_3_0::types as message_types;
+use bindings::wasmcloud::messaging0_2_0::{handler, types};
+use bindings::wasmcloud::messaging0_3_0::{incoming_handler, types as types_v0_3};
+
+/// Messaging interface exported by a linked component, taken from the
+/// interfaces named in the link
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum MessagingVersion {
+    /// `wasmcloud:messaging/handler@0.2.0`
+    #[default]
+    V0_2,
+    /// `wasmcloud:messaging/incoming-handler@0.3.0`
+    V0_3,
+}
+
+impl MessagingVersion {
+    /// Pick the version from the link's interface list, defaulting to 0.2.0
+    fn from_interfaces(interfaces: &[String]) -> Self {
+        if interfaces.iter().any(|i| i == "incoming-handler") {
+            MessagingVersion::V0_3
+        } else {
+            if !interfaces.iter().any(|i| i == "handler") {
+                warn!(
+                    ?interfaces,
+                    "link names no messaging handler interface, assuming handler@0.2.0"
+                );
+            }
+            MessagingVersion::V0_2
+        }
+    }
+}
+
+/// A 0.3.0 `message` held by the provider
+#[derive(Debug, Default)]
+struct HeldMessage {
+    topic: Option<String>,
+    content_type: Option<String>,
+    data: Bytes,
+    metadata: Vec<(String, String)>,
+}
+
+/// 0.3.0 `message` resources the provider owns, keyed by handle.
+///
+/// The provider exports `wasmcloud:messaging/types@0.3.0`, so a component
+/// reads the message it is handed by calling back into the provider. A message
+/// is held from its creation until the `incoming-handler.handle` call it was
+/// created for returns.
+#[derive(Default)]
+struct MessageStore {
+    next_handle: AtomicU64,
+    messages: Mutex<HashMap<Bytes, HeldMessage>>,
+}
+
+impl MessageStore {
+    /// Hold `message` and return its handle
+    fn insert(&self, message: HeldMessage) -> Bytes {
+        let handle = self.next_handle.fetch_add(1, Ordering::Relaxed);
+        let handle = Bytes::from(handle.to_string());
+        self.messages
+            .lock()
+            .expect("message store lock poisoned")
+            .insert(handle.clone(), message);
+        handle
+    }
+
+    fn remove(&self, handle: &Bytes) -> Option<HeldMessage> {
+        self.messages
+            .lock()
+            .expect("message store lock poisoned")
+            .remove(handle)
+    }
+
+    /// Run `f` on the message behind `handle`
+    fn with<T>(
+        &self,
+        handle: ResourceBorrow<message_types::Message>,
+        f: impl FnOnce(&mut HeldMessage) -> T,
+    ) -> anyhow::Result<T> {
+        let handle = Bytes::from(handle);
+        let mut messages = self.messages.lock().expect("message store lock poisoned");
+        let message = messages
+            .get_mut(&handle)
+            .context("unknown message handle")?;
+        Ok(f(message))
+    }
+}
+
+/// wRPC client for a linked component, created on the first delivery and
+/// reused for every later one. Creating a client subscribes a fresh NATS inbox,
+/// which is too costly to repeat per frame.
+#[derive(Clone)]
+struct ComponentClient {
+    component_id: Arc<str>,
+    client: Arc<OnceCell<WrpcClient>>,
+    /// Messages handed to the component while it handles them
+    messages: Arc<MessageStore>,
+}
+
+impl ComponentClient {
+    fn new(component_id: &str, messages: Arc<MessageStore>) -> Self {
+        ComponentClient {
+            component_id: Arc::from(component_id),
+            client: Arc::default(),
+            messages,
+        }
+    }
+
+    /// Get the cached client, creating it if this is the first call
+    async fn get(&self) -> anyhow::Result<&WrpcClient> {
+        self.client
+            .get_or_try_init(|| async {
+                get_connection()
+                    .get_wrpc_client(&self.component_id)
+                    .await
+                    .context("failed to get wrpc client")
+            })
+            .await
+    }
+}
+
+/// A link's spool of undeliverable messages and the signal that wakes its
+/// replay task. Spool calls do file I/O, so they run on the blocking pool.
+struct LinkSpool {
+    spool: Arc<Mutex<Spool>>,
+    /// Messages waiting in the spool as of the last spool call, so delivery
+    /// can tell whether to bypass it without a trip to the blocking pool
+    pending: Arc<AtomicU64>,
+    notify: Notify,
+}
+
+impl LinkSpool {
+    /// Open the spool for `component_id` under the link's `spool_dir`, or
+    /// `None` if spooling is disabled
+    async fn open(config: &ConnectionConfig, component_id: &str) -> anyhow::Result<Option<Self>> {
+        if config.spool_dir.is_empty() {
+            return Ok(None);
+        }
+        let name: String = component_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let dir = Path::new(&config.spool_dir).join(name);
+        let spool = {
+            let (dir, config) = (dir.clone(), config.clone());
+            tokio::task::spawn_blocking(move || Spool::open(&dir, &config)).await?
+        }
+        .with_context(|| format!("failed to open spool in {}", dir.display()))?;
+        info!(dir = %dir.display(), status = ?spool.status(), "spool opened");
+        Ok(Some(LinkSpool {
+            pending: Arc::new(AtomicU64::new(spool.pending())),
+            spool: Arc::new(Mutex::new(spool)),
+            notify: Notify::new(),
+        }))
+    }
+
+    /// Run `f` on the spool on the blocking pool
+    async fn run<T>(&self, f: impl FnOnce(&mut Spool) -> T + Send + 'static) -> T
+    where
+        T: Send + 'static,
+    {
+        let (spool, pending) = (self.spool.clone(), self.pending.clone());
+        tokio::task::spawn_blocking(move || {
+            let mut spool = spool.lock().expect("spool lock poisoned");
+            let result = f(&mut spool);
+            pending.store(spool.pending(), Ordering::Release);
+            result
+        })
+        .await
+        .expect("spool task panicked")
+    }
+
+    /// Whether no messages are waiting to be replayed
+    fn is_empty(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+
+    /// Append a message and wake the replay task
+    async fn push(
+        &self,
+        message: types::BrokerMessage,
+        headers: Vec<(String, String)>,
+    ) -> anyhow::Result<()> {
+        self.run(move |spool| {
+            spool.append(&message.subject, &headers, &message.body, SystemTime::now())
+        })
+        .await?;
+        self.notify.notify_one();
+        Ok(())
+    }
+}
+
+/// Reply body of a link status request
+#[derive(Debug, Serialize)]
+struct LinkStatus {
+    #[serde(flatten)]
+    stats: LinkStatsSnapshot,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spool: Option<SpoolStatus>,
+}
 
 /// State for a single stream connection
 struct ConnectionState {
     /// Configuration for this connection
     _config: ConnectionConfig,
+    /// Messaging interface version of the linked component
+    _messaging_version: MessagingVersion,
+    /// wRPC client shared by the connection's deliveries
+    _client: ComponentClient,
+    /// Counters of frames the stream client truncated, dropped or delayed
+    stats: Arc<LinkStats>,
+    /// Spool of messages awaiting delivery, when `spool_dir` is set
+    spool: Option<Arc<LinkSpool>>,
     /// Handle to the background stream task
     _task_handle: tokio::task::JoinHandle<()>,
+    /// Handle to the spool replay task
+    _replay_handle: Option<tokio::task::JoinHandle<()>>,
     /// Shutdown signal sender — dropping this triggers stream shutdown
     _shutdown_tx: tokio::sync::oneshot::Sender<()>,
 }
 
+impl ConnectionState {
+    /// Stop the stream and wait until the connection's tasks have handed off
+    /// what they queued and no longer touch its spool
+    async fn stop(self) {
+        if let Some(replay) = self._replay_handle {
+            replay.abort();
+            let _ = replay.await;
+        }
+        drop(self._shutdown_tx);
+        let _ = self._task_handle.await;
+        // A spool call already on the blocking pool runs to completion
+        if let Some(spool) = self.spool {
+            spool.run(|_| ()).await;
+        }
+    }
+}
+
 /// TCP/UDP stream listen provider implementation
 #[derive(Default, Clone)]
 pub struct TcpUdpStreamProvider {
@@ -40,6 +295,8 @@ pub struct TcpUdpStreamProvider {
     connections: Arc<RwLock<HashMap<String, ConnectionState>>>,
     /// Default configuration used when link config is empty
     default_config: ConnectionConfig,
+    /// 0.3.0 messages currently handed to components
+    messages: Arc<MessageStore>,
 }
 
 impl TcpUdpStreamProvider {
@@ -96,10 +353,14 @@ impl Provider for TcpUdpStreamProvider {
     async fn receive_link_config_as_target(
         &self,
         SdkLinkConfig {
-            source_id, config, ..
+            source_id,
+            config,
+            wit_metadata: (_, _, interfaces),
+            ..
         }: SdkLinkConfig<'_>,
     ) -> anyhow::Result<()> {
         info!("Received link configuration from component: {}", source_id);
+        let messaging_version = MessagingVersion::from_interfaces(interfaces);
 
         // Parse link configuration
         let link_config = if config.is_empty() {
@@ -108,47 +369,107 @@ impl Provider for TcpUdpStreamProvider {
             self.default_config.merge(ConnectionConfig::from(config))
         };
 
+        let mut decoder = Decoder::from_config(&link_config)?;
+        let parser = RegexParser::from_config(&link_config)?;
+
+        // A new config for a linked component replaces its connection, which
+        // must stop before the spool directory is reopened
+        let previous = self.connections.write().await.remove(source_id);
+        if let Some(previous) = previous {
+            info!("Replacing stream connection for component: {}", source_id);
+            previous.stop().await;
+        }
+
+        let spool = LinkSpool::open(&link_config, source_id)
+            .await?
+            .map(Arc::new);
+
         info!(
             protocol = ?link_config.protocol,
             addr = %link_config.addr(),
+            messaging = ?messaging_version,
             "Starting stream client for component: {}", source_id
         );
 
         // Clone what we need for the task
         let config_clone = link_config.clone();
-        let source_id_clone = source_id.to_string();
+        let client = ComponentClient::new(source_id, self.messages.clone());
+        let client_clone = client.clone();
         let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let stream_client = StreamClient::new(config_clone.clone());
+        let stats = stream_client.stats();
+        let replay_handle = spool
+            .clone()
+            .map(|spool| tokio::spawn(run_spool_replay(spool, client.clone(), messaging_version)));
+        let spool_clone = spool.clone();
 
         // Spawn stream client task
         let task_handle = tokio::spawn(async move {
-            let stream_client = StreamClient::new(config_clone.clone());
-
-            // Create message handler that forwards to the component via wRPC
-            // using the standard wasmcloud:messaging interface
-            let addr = config_clone.addr();
-            let result = stream_client
-                .run(
-                    move |data| {
-                        // Convert stream message to a standard broker-message
-                        let message = create_broker_message(data, &addr);
-
-                        // Spawn a task to send message to component
-                        let source = source_id_clone.clone();
-                        tokio::spawn(async move {
-                            if let Err(e) = send_message_to_component(&source, message).await {
-                                error!("Failed to send message to component {}: {}", source, e);
-                            }
-                        });
-
-                        Ok(())
-                    },
-                    shutdown_rx,
-                )
-                .await;
+            let result = if config_clone.batching_enabled() {
+                // Frames are handed to a batcher task that delivers each batch
+                // as one message; it flushes what is pending once the stream
+                // client stops and drops the sender.
+                let (frame_tx, frame_rx) = mpsc::channel(DELIVERY_QUEUE_LEN);
+                let batcher = tokio::spawn(run_batcher(
+                    frame_rx,
+                    config_clone.clone(),
+                    client_clone,
+                    messaging_version,
+                    spool_clone,
+                ));
+                let result = stream_client
+                    .run(
+                        DeliveryQueue::new(frame_tx, move |frame| {
+                            process_frame(decoder.as_mut(), parser.as_ref(), frame)
+                        }),
+                        shutdown_rx,
+                    )
+                    .await;
+                let _ = batcher.await;
+                result
+            } else {
+                // Messages are handed to a delivery task that sends them to the
+                // component one at a time, in order; it drains what is queued
+                // once the stream client stops and drops the sender.
+                let (message_tx, message_rx) = mpsc::channel(DELIVERY_QUEUE_LEN);
+                let delivery = tokio::spawn(run_delivery(
+                    message_rx,
+                    client_clone,
+                    messaging_version,
+                    spool_clone,
+                ));
+                let addr = config_clone.addr();
+                let metadata = config_clone.metadata.clone();
+                let envelope = config_clone.envelope;
+                let result = stream_client
+                    .run(
+                        DeliveryQueue::new(message_tx, move |frame| {
+                            let frame = process_frame(decoder.as_mut(), parser.as_ref(), frame)?;
+
+                            // Convert stream message to a standard broker-message
+                            let meta = frame.meta.clone();
+                            let message =
+                                match create_broker_message(frame, &addr, &metadata, envelope) {
+                                    Ok(message) => message,
+                                    Err(e) => {
+                                        error!("Failed to encode message from {}: {}", addr, e);
+                                        return None;
+                                    }
+                                };
+                            let headers = message_headers(messaging_version, &message, &meta);
+                            Some((message, headers))
+                        }),
+                        shutdown_rx,
+                    )
+                    .await;
+                let _ = delivery.await;
+                result
+            };
 
             if let Err(e) = result {
                 error!("Stream client error: {}", e);
             }
+            info!(stats = ?stream_client.stats().snapshot(), "Stream client stopped");
         });
 
         // Store connection state
@@ -156,7 +477,12 @@ impl Provider for TcpUdpStreamProvider {
             source_id.to_string(),
             ConnectionState {
                 _config: link_config,
+                _messaging_version: messaging_version,
+                _client: client,
+                stats,
+                spool,
                 _task_handle: task_handle,
+                _replay_handle: replay_handle,
                 _shutdown_tx: shutdown_tx,
             },
         );
@@ -170,10 +496,17 @@ impl Provider for TcpUdpStreamProvider {
         let source_id = link.get_source_id();
         info!("Deleting link with component: {}", source_id);
 
-        // Remove connection state (task will be cancelled)
+        // Remove connection state (task will be cancelled). This drops the
+        // cached wRPC client, so a new link starts with a fresh one.
         if let Some(state) = self.connections.write().await.remove(source_id) {
-            info!("Stream connection closed for component: {}", source_id);
+            info!(
+                stats = ?state.stats.snapshot(),
+                "Stream connection closed for component: {}", source_id
+            );
             state._task_handle.abort();
+            if let Some(replay) = state._replay_handle {
+                replay.abort();
+            }
         } else {
             warn!("No connection found for component: {}", source_id);
         }
@@ -190,6 +523,9 @@ impl Provider for TcpUdpStreamProvider {
         for (source_id, state) in connections.drain() {
             info!("Closing stream connection for component: {}", source_id);
             state._task_handle.abort();
+            if let Some(replay) = state._replay_handle {
+                replay.abort();
+            }
         }
 
         info!("TCP/UDP stream provider shutdown complete");
@@ -200,10 +536,10 @@ impl Provider for TcpUdpStreamProvider {
 /// Implement the `wasmcloud:messaging/consumer` interface.
 ///
 /// This provider is **receive-only** (unidirectional). Publishing and request
-/// methods are not supported in this initial release (reply-back deferred).
-impl bindings::exports::wasmcloud::messaging::consumer::Handler<Option<SdkContext>>
-    for TcpUdpStreamProvider
-{
+/// methods are not supported in this initial release (reply-back deferred),
+/// except for requests on [`STATUS_SUBJECT`], which return the calling
+/// component's link stats.
+impl consumer::Handler<Option<SdkContext>> for TcpUdpStreamProvider {
     async fn publish(
         &self,
         _ctx: Option<SdkContext>,
@@ -218,11 +554,16 @@ impl bindings::exports::wasmcloud::messaging::consumer::Handler<Option<SdkContex
 
     async fn request(
         &self,
-        _ctx: Option<SdkContext>,
-        _subject: String,
+        ctx: Option<SdkContext>,
+        subject: String,
         _body: Bytes,
         _timeout_ms: u32,
     ) -> anyhow::Result<Result<types::BrokerMessage, String>> {
+        if subject == STATUS_SUBJECT {
+            let component = ctx.and_then(|ctx| ctx.component).unwrap_or_default();
+            return Ok(self.link_status(&component).await);
+        }
+
         // Reply-back / request is deferred
         Ok(Err(
             "request is not supported: this provider is receive-only (reply-back deferred)"
@@ -231,32 +572,447 @@ impl bindings::exports::wasmcloud::messaging::consumer::Handler<Option<SdkContex
     }
 }
 
+/// Implement `wasmcloud:messaging/types@0.3.0` over the messages the provider
+/// hands to 0.3.0 components.
+///
+/// Messages are only created by the provider; like publishing, creating one
+/// from a component and connecting a client are not supported.
+impl message_types::Handler<Option<SdkContext>> for TcpUdpStreamProvider {}
+
+impl message_types::HandlerClient<Option<SdkContext>> for TcpUdpStreamProvider {
+    async fn connect(
+        &self,
+        _ctx: Option<SdkContext>,
+        _name: String,
+    ) -> anyhow::Result<Result<ResourceOwn<message_types::Client>, message_types::Error>> {
+        Ok(Err(message_types::Error::Other(
+            "connect is not supported: this provider is receive-only".to_string(),
+        )))
+    }
+
+    async fn disconnect(
+        &self,
+        _ctx: Option<SdkContext>,
+        _self_: ResourceBorrow<message_types::Client>,
+    ) -> anyhow::Result<Result<(), message_types::Error>> {
+        Ok(Err(message_types::Error::Other(
+            "disconnect is not supported: this provider is receive-only".to_string(),
+        )))
+    }
+}
+
+impl message_types::HandlerMessage<Option<SdkContext>> for TcpUdpStreamProvider {
+    async fn new(
+        &self,
+        _ctx: Option<SdkContext>,
+        _data: Bytes,
+    ) -> anyhow::Result<ResourceOwn<message_types::Message>> {
+        anyhow::bail!("creating messages is not supported: this provider is receive-only")
+    }
+
+    async fn topic(
+        &self,
+        _ctx: Option<SdkContext>,
+        self_: ResourceBorrow<message_types::Message>,
+    ) -> anyhow::Result<Option<String>> {
+        self.messages.with(self_, |message| message.topic.clone())
+    }
+
+    async fn content_type(
+        &self,
+        _ctx: Option<SdkContext>,
+        self_: ResourceBorrow<message_types::Message>,
+    ) -> anyhow::Result<Option<String>> {
+        self.messages
+            .with(self_, |message| message.content_type.clone())
+    }
+
+    async fn set_content_type(
+        &self,
+        _ctx: Option<SdkContext>,
+        self_: ResourceBorrow<message_types::Message>,
+        content_type: String,
+    ) -> anyhow::Result<()> {
+        self.messages
+            .with(self_, |message| message.content_type = Some(content_type))
+    }
+
+    async fn data(
+        &self,
+        _ctx: Option<SdkContext>,
+        self_: ResourceBorrow<message_types::Message>,
+    ) -> anyhow::Result<Bytes> {
+        self.messages.with(self_, |message| message.data.clone())
+    }
+
+    async fn set_data(
+        &self,
+        _ctx: Option<SdkContext>,
+        self_: ResourceBorrow<message_types::Message>,
+        data: Bytes,
+    ) -> anyhow::Result<()> {
+        self.messages.with(self_, |message| message.data = data)
+    }
+
+    async fn metadata(
+        &self,
+        _ctx: Option<SdkContext>,
+        self_: ResourceBorrow<message_types::Message>,
+    ) -> anyhow::Result<Option<Vec<(String, String)>>> {
+        self.messages
+            .with(self_, |message| Some(message.metadata.clone()))
+    }
+
+    async fn add_metadata(
+        &self,
+        _ctx: Option<SdkContext>,
+        self_: ResourceBorrow<message_types::Message>,
+        key: String,
+        value: String,
+    ) -> anyhow::Result<()> {
+        self.messages
+            .with(self_, |message| message.metadata.push((key, value)))
+    }
+
+    async fn set_metadata(
+        &self,
+        _ctx: Option<SdkContext>,
+        self_: ResourceBorrow<message_types::Message>,
+        meta: Vec<(String, String)>,
+    ) -> anyhow::Result<()> {
+        self.messages.with(self_, |message| message.metadata = meta)
+    }
+
+    async fn remove_metadata(
+        &self,
+        _ctx: Option<SdkContext>,
+        self_: ResourceBorrow<message_types::Message>,
+        key: String,
+    ) -> anyhow::Result<()> {
+        self.messages
+            .with(self_, |message| message.metadata.retain(|(k, _)| *k != key))
+    }
+}
+
+impl TcpUdpStreamProvider {
+    /// Stats of the link from `component`, encoded as a JSON reply
+    async fn link_status(&self, component: &str) -> Result<types::BrokerMessage, String> {
+        let (stats, spool) = {
+            let connections = self.connections.read().await;
+            let state = connections
+                .get(component)
+                .ok_or_else(|| format!("no link found for component {component:?}"))?;
+            (state.stats.snapshot(), state.spool.clone())
+        };
+        let spool = match spool {
+            Some(spool) => Some(spool.run(|spool| spool.status()).await),
+            None => None,
+        };
+        let status = LinkStatus { stats, spool };
+        let body = serde_json::to_vec(&status).map_err(|e| e.to_string())?;
+        Ok(types::BrokerMessage {
+            subject: STATUS_SUBJECT.to_string(),
+            body: body.into(),
+            reply_to: None,
+        })
+    }
+}
+
+/// Run the link's decoder and then its regex parser over a frame, where
+/// configured. Returns `None` when the frame should be dropped.
+fn process_frame(
+    decoder: Option<&mut Decoder>,
+    parser: Option<&RegexParser>,
+    frame: Frame,
+) -> Option<Frame> {
+    let frame = match decoder {
+        Some(decoder) => decoder.decode(frame)?,
+        None => frame,
+    };
+    match parser {
+        Some(parser) => parser.apply(frame),
+        None => Some(frame),
+    }
+}
+
 /// Create a broker-message from raw stream data.
 ///
 /// The subject is set to "stream.<protocol>://<host:port>" so the component knows
-/// which stream connection the message originated from.
+/// which stream connection the message originated from, unless the transport
+/// supplied its own subject for the frame (e.g. an SSE event type).
 /// The body contains the raw bytes of the received message.
-fn create_broker_message(data: Vec<u8>, addr: &str) -> types::BrokerMessage {
-    types::BrokerMessage {
-        subject: format!("stream.{}", addr),
-        body: data.into(),
+///
+/// Receive metadata is appended to the subject or wrapped around the body
+/// according to the link's [`MetadataMode`] and [`EnvelopeFormat`].
+fn create_broker_message(
+    frame: Frame,
+    addr: &str,
+    metadata: &MetadataMode,
+    envelope: EnvelopeFormat,
+) -> anyhow::Result<types::BrokerMessage> {
+    let subject = frame
+        .subject
+        .clone()
+        .unwrap_or_else(|| format!("stream.{}", addr));
+    let (subject, body) = match metadata {
+        MetadataMode::None => (subject, frame.data),
+        MetadataMode::Subject => (subject_with_metadata(&subject, &frame.meta), frame.data),
+        MetadataMode::Envelope => (subject, encode_envelope(&frame, envelope)?),
+    };
+    Ok(types::BrokerMessage {
+        subject,
+        body: body.into(),
+        reply_to: None,
+    })
+}
+
+/// Create one broker-message from a batch of frames.
+///
+/// The subject is taken from the first frame as in [`create_broker_message`];
+/// with `metadata=subject` it carries the first frame's metadata. The body is
+/// encoded according to the link's [`BatchFormat`], with each item wrapped in
+/// an envelope when `metadata=envelope`.
+fn create_batch_message(
+    frames: &[Frame],
+    addr: &str,
+    metadata: &MetadataMode,
+    envelope: EnvelopeFormat,
+    format: BatchFormat,
+) -> anyhow::Result<types::BrokerMessage> {
+    let first = frames.first().context("empty batch")?;
+    let subject = first
+        .subject
+        .clone()
+        .unwrap_or_else(|| format!("stream.{}", addr));
+    let subject = match metadata {
+        MetadataMode::Subject => subject_with_metadata(&subject, &first.meta),
+        MetadataMode::None | MetadataMode::Envelope => subject,
+    };
+    let body = encode_batch(frames, format, envelope, |frame| match metadata {
+        MetadataMode::Envelope => encode_envelope(frame, envelope),
+        MetadataMode::None | MetadataMode::Subject => Ok(frame.data.clone()),
+    })?;
+    Ok(types::BrokerMessage {
+        subject,
+        body: body.into(),
         reply_to: None,
+    })
+}
+
+/// Queues a link's frames for its delivery task, converted by `convert`;
+/// frames it maps to `None` are skipped. While the queue is full the
+/// stream's reader waits here, so a slow component applies backpressure to
+/// the feed instead of growing the queue.
+struct DeliveryQueue<T, C> {
+    tx: mpsc::Sender<T>,
+    convert: C,
+}
+
+impl<T, C> DeliveryQueue<T, C> {
+    fn new(tx: mpsc::Sender<T>, convert: C) -> Self {
+        DeliveryQueue { tx, convert }
     }
 }
 
-/// Send message to component via wRPC using the standard messaging handler
-async fn send_message_to_component(
-    component_id: &str,
+impl<T, C> FrameSink for DeliveryQueue<T, C>
+where
+    T: Send,
+    C: FnMut(Frame) -> Option<T>,
+{
+    fn handle(&mut self, frame: Frame) -> impl Future<Output = anyhow::Result<()>> + Send {
+        let item = (self.convert)(frame);
+        let tx = &self.tx;
+        async move {
+            let Some(item) = item else {
+                return Ok(());
+            };
+            tx.send(item)
+                .await
+                .map_err(|_| anyhow::anyhow!("delivery task stopped"))
+        }
+    }
+}
+
+/// Coalesce frames from `frames` into batches and deliver each batch as one
+/// message, in order, until the sender is dropped and the last partial batch
+/// is flushed
+async fn run_batcher(
+    mut frames: mpsc::Receiver<Frame>,
+    config: ConnectionConfig,
+    client: ComponentClient,
+    version: MessagingVersion,
+    spool: Option<Arc<LinkSpool>>,
+) {
+    let addr = config.addr();
+    let mut batcher = Batcher::new(&config);
+    let mut deadline: Option<Instant> = None;
+
+    loop {
+        let (batches, closed) = tokio::select! {
+            frame = frames.recv() => match frame {
+                Some(frame) => {
+                    let was_empty = batcher.is_empty();
+                    let batches = batcher.push(frame);
+                    if batcher.is_empty() {
+                        deadline = None;
+                    } else if was_empty || !batches.is_empty() {
+                        deadline = Some(Instant::now() + batcher.max_delay());
+                    }
+                    (batches, false)
+                }
+                None => (vec![batcher.take()], true),
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                deadline = None;
+                (vec![batcher.take()], false)
+            }
+        };
+
+        for batch in batches.into_iter().filter(|b| !b.is_empty()) {
+            let message = match create_batch_message(
+                &batch,
+                &addr,
+                &config.metadata,
+                config.envelope,
+                config.batch_format,
+            ) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("Failed to encode batch from {}: {}", addr, e);
+                    continue;
+                }
+            };
+            let headers = message_headers(version, &message, &batch[0].meta);
+            if let Err(e) = deliver(&client, version, message, headers, spool.as_deref()).await {
+                error!(
+                    "Failed to send batch to component {}: {}",
+                    client.component_id, e
+                );
+            }
+        }
+        if closed {
+            break;
+        }
+    }
+}
+
+/// Headers for a message to a `wasmcloud:messaging@0.3.0` component, built
+/// from the metadata of the (first) frame it carries. 0.2.0 messages have no
+/// headers.
+fn message_headers(
+    version: MessagingVersion,
+    message: &types::BrokerMessage,
+    meta: &FrameMetadata,
+) -> Vec<(String, String)> {
+    match version {
+        MessagingVersion::V0_2 => Vec::new(),
+        MessagingVersion::V0_3 => metadata::headers(&message.subject, meta),
+    }
+}
+
+/// Send a message to the component. With a spool, the message is spooled
+/// instead if delivery fails or earlier messages are still waiting to be
+/// replayed, so the component receives them in order.
+async fn deliver(
+    client: &ComponentClient,
+    version: MessagingVersion,
     message: types::BrokerMessage,
+    headers: Vec<(String, String)>,
+    spool: Option<&LinkSpool>,
 ) -> anyhow::Result<()> {
-    let client = wasmcloud_provider_sdk::get_connection()
-        .get_wrpc_client(component_id)
-        .await
-        .context("failed to get wrpc client")?;
+    let Some(spool) = spool else {
+        return send_message_to_component(client, version, &message, &headers).await;
+    };
+    if spool.is_empty() {
+        match send_message_to_component(client, version, &message, &headers).await {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!(error = %e, "delivery failed, spooling message"),
+        }
+    }
+    spool.push(message, headers).await
+}
+
+/// Deliver the link's messages from `messages` one at a time, in the order
+/// they were received, until the sender is dropped
+async fn run_delivery(
+    mut messages: mpsc::Receiver<(types::BrokerMessage, Vec<(String, String)>)>,
+    client: ComponentClient,
+    version: MessagingVersion,
+    spool: Option<Arc<LinkSpool>>,
+) {
+    while let Some((message, headers)) = messages.recv().await {
+        if let Err(e) = deliver(&client, version, message, headers, spool.as_deref()).await {
+            error!(
+                "Failed to send message to component {}: {}",
+                client.component_id, e
+            );
+        }
+    }
+}
 
-    match handler::handle_message(&client, None, &message).await {
+/// Replay spooled messages in order whenever the spool has any, pausing for
+/// [`SPOOL_RETRY_DELAY`] while the component is unreachable
+async fn run_spool_replay(
+    spool: Arc<LinkSpool>,
+    client: ComponentClient,
+    version: MessagingVersion,
+) {
+    loop {
+        let next = spool.run(|spool| spool.peek(SystemTime::now())).await;
+        match next {
+            Ok(Some(spooled)) => {
+                let message = types::BrokerMessage {
+                    subject: spooled.subject,
+                    body: spooled.body.into(),
+                    reply_to: None,
+                };
+                match send_message_to_component(&client, version, &message, &spooled.headers).await
+                {
+                    Ok(()) => {
+                        let advanced = spool.run(|spool| spool.advance()).await;
+                        if let Err(e) = advanced {
+                            error!(error = %e, "failed to advance spool cursor");
+                            tokio::time::sleep(SPOOL_RETRY_DELAY).await;
+                        }
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "spool replay failed, retrying");
+                        tokio::time::sleep(SPOOL_RETRY_DELAY).await;
+                    }
+                }
+            }
+            Ok(None) => spool.notify.notified().await,
+            Err(e) => {
+                error!(error = %e, "failed to read spool");
+                tokio::time::sleep(SPOOL_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Send message to component via wRPC using the messaging handler the link
+/// names
+async fn send_message_to_component(
+    client: &ComponentClient,
+    version: MessagingVersion,
+    message: &types::BrokerMessage,
+    headers: &[(String, String)],
+) -> anyhow::Result<()> {
+    let component_id = &client.component_id;
+    match invoke_handler(
+        client.get().await?,
+        &client.messages,
+        version,
+        message,
+        headers,
+    )
+    .await
+    {
         Ok(Ok(_)) => {
-            info!("Message successfully sent to component {}", component_id);
+            info!(
+                messaging = ?version,
+                "Message successfully sent to component {}", component_id
+            );
             Ok(())
         }
         Ok(Err(e)) => {
@@ -270,9 +1026,46 @@ async fn send_message_to_component(
     }
 }
 
+/// Invoke the component's messaging handler.
+///
+/// 0.2.0 components get the broker-message as is. For 0.3.0 components the
+/// provider holds a `message` with the body, the subject as its topic and
+/// `headers` as its metadata in `messages`, and hands its handle to
+/// `incoming-handler.handle`.
+async fn invoke_handler<C>(
+    wrpc: &C,
+    messages: &MessageStore,
+    version: MessagingVersion,
+    message: &types::BrokerMessage,
+    headers: &[(String, String)],
+) -> anyhow::Result<Result<(), String>>
+where
+    C: Invoke,
+    C::Context: Default,
+{
+    match version {
+        MessagingVersion::V0_2 => {
+            handler::handle_message(wrpc, C::Context::default(), message).await
+        }
+        MessagingVersion::V0_3 => {
+            let handle = messages.insert(HeldMessage {
+                topic: Some(message.subject.clone()),
+                content_type: None,
+                data: message.body.clone(),
+                metadata: headers.to_vec(),
+            });
+            let resource = ResourceOwn::<types_v0_3::Message>::from(handle.clone());
+            let result = incoming_handler::handle(wrpc, C::Context::default(), &resource).await;
+            messages.remove(&handle);
+            Ok(result?.map_err(|e| e.to_string()))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::metadata::Envelope;
 
     #[test]
     fn test_provider_creation() {
@@ -288,6 +1081,46 @@ mod tests {
         assert!(map.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_delivery_queue_waits_for_room() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut queue = DeliveryQueue::new(tx, |frame: Frame| {
+            (frame.data != b"skip").then_some(frame.data)
+        });
+        queue.handle(b"one".to_vec().into()).await.unwrap();
+        queue.handle(b"skip".to_vec().into()).await.unwrap();
+
+        // A full queue holds the reader back until the delivery task catches up
+        let full = tokio::time::timeout(
+            Duration::from_millis(50),
+            queue.handle(b"two".to_vec().into()),
+        )
+        .await;
+        assert!(full.is_err());
+        assert_eq!(rx.recv().await.unwrap(), b"one");
+        queue.handle(b"three".to_vec().into()).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), b"three");
+
+        drop(rx);
+        assert!(queue.handle(b"four".to_vec().into()).await.is_err());
+    }
+
+    #[test]
+    fn test_messaging_version_from_interfaces() {
+        assert_eq!(
+            MessagingVersion::from_interfaces(&["handler".to_string()]),
+            MessagingVersion::V0_2
+        );
+        assert_eq!(
+            MessagingVersion::from_interfaces(&["incoming-handler".to_string()]),
+            MessagingVersion::V0_3
+        );
+        assert_eq!(
+            MessagingVersion::from_interfaces(&[]),
+            MessagingVersion::V0_2
+        );
+    }
+
     #[tokio::test]
     async fn test_publish_returns_error() {
         let provider = TcpUdpStreamProvider::default();
@@ -296,11 +1129,9 @@ mod tests {
             body: Bytes::from("hello"),
             reply_to: None,
         };
-        let result = bindings::exports::wasmcloud::messaging::consumer::Handler::publish(
-            &provider, None, msg,
-        )
-        .await
-        .unwrap();
+        let result = consumer::Handler::publish(&provider, None, msg)
+            .await
+            .unwrap();
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("receive-only"));
     }
@@ -308,7 +1139,7 @@ mod tests {
     #[tokio::test]
     async fn test_request_returns_error() {
         let provider = TcpUdpStreamProvider::default();
-        let result = bindings::exports::wasmcloud::messaging::consumer::Handler::request(
+        let result = consumer::Handler::request(
             &provider,
             None,
             "test".to_string(),
@@ -321,12 +1152,370 @@ mod tests {
         assert!(result.unwrap_err().contains("receive-only"));
     }
 
+    #[tokio::test]
+    async fn test_request_link_status() {
+        let provider = TcpUdpStreamProvider::default();
+        let stats = Arc::new(LinkStats::default());
+        LinkStats::incr(&stats.frames_rate_dropped);
+        LinkStats::add(&stats.bytes_rate_dropped, 42);
+        let (shutdown_tx, _shutdown_rx) = tokio::sync::oneshot::channel();
+        provider.connections.write().await.insert(
+            "component-a".to_string(),
+            ConnectionState {
+                _config: ConnectionConfig::default(),
+                _messaging_version: MessagingVersion::V0_2,
+                _client: ComponentClient::new("component-a", Arc::default()),
+                stats,
+                spool: None,
+                _task_handle: tokio::spawn(async {}),
+                _replay_handle: None,
+                _shutdown_tx: shutdown_tx,
+            },
+        );
+
+        let request = |component: &str| {
+            consumer::Handler::request(
+                &provider,
+                Some(SdkContext {
+                    component: Some(component.to_string()),
+                    ..Default::default()
+                }),
+                STATUS_SUBJECT.to_string(),
+                Bytes::new(),
+                1000,
+            )
+        };
+        let reply = request("component-a").await.unwrap().unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&reply.body).unwrap();
+        assert_eq!(status["frames_rate_dropped"], 1);
+        assert_eq!(status["bytes_rate_dropped"], 42);
+        assert_eq!(status["frames_discarded"], 0);
+        assert!(request("component-b").await.unwrap().is_err());
+    }
+
     #[test]
     fn test_create_broker_message() {
         let data = b"hello world".to_vec();
-        let msg = create_broker_message(data.clone(), "127.0.0.1:9000");
+        let msg = create_broker_message(
+            data.clone().into(),
+            "127.0.0.1:9000",
+            &MetadataMode::None,
+            EnvelopeFormat::None,
+        )
+        .unwrap();
         assert_eq!(msg.subject, "stream.127.0.0.1:9000");
         assert_eq!(msg.body.as_ref(), b"hello world");
         assert!(msg.reply_to.is_none());
     }
+
+    #[test]
+    fn test_create_broker_message_with_frame_subject() {
+        let frame = Frame {
+            data: b"{}".to_vec(),
+            subject: Some("position".to_string()),
+            ..Default::default()
+        };
+        let msg = create_broker_message(
+            frame,
+            "http://127.0.0.1:9000/",
+            &MetadataMode::None,
+            EnvelopeFormat::None,
+        )
+        .unwrap();
+        assert_eq!(msg.subject, "position");
+    }
+
+    #[test]
+    fn test_create_broker_message_with_metadata() {
+        let mut frame = Frame::from(b"hi".to_vec());
+        frame.meta.peer = Some("10.0.0.5:4001".to_string());
+        frame.meta.seq = 7;
+        frame.meta.connection_id = 2;
+
+        let msg = create_broker_message(
+            frame.clone(),
+            "10.0.0.5:4001",
+            &MetadataMode::Subject,
+            EnvelopeFormat::None,
+        )
+        .unwrap();
+        assert_eq!(
+            msg.subject,
+            "stream.10.0.0.5:4001;peer=10.0.0.5:4001;seq=7;conn=2"
+        );
+        assert_eq!(msg.body.as_ref(), b"hi");
+
+        let msg = create_broker_message(
+            frame,
+            "10.0.0.5:4001",
+            &MetadataMode::Envelope,
+            EnvelopeFormat::Json,
+        )
+        .unwrap();
+        assert_eq!(msg.subject, "stream.10.0.0.5:4001");
+        let envelope: Envelope<String> = serde_json::from_slice(&msg.body).unwrap();
+        assert_eq!(envelope.payload, "aGk=");
+        assert_eq!(envelope.seq, 7);
+    }
+
+    #[test]
+    fn test_create_batch_message() {
+        let frames: Vec<Frame> = vec![b"a".to_vec().into(), b"b".to_vec().into()];
+        let msg = create_batch_message(
+            &frames,
+            "127.0.0.1:9000",
+            &MetadataMode::None,
+            EnvelopeFormat::None,
+            BatchFormat::Lines,
+        )
+        .unwrap();
+        assert_eq!(msg.subject, "stream.127.0.0.1:9000");
+        assert_eq!(msg.body.as_ref(), b"a\nb");
+
+        let msg = create_batch_message(
+            &frames,
+            "127.0.0.1:9000",
+            &MetadataMode::Envelope,
+            EnvelopeFormat::Json,
+            BatchFormat::Lines,
+        )
+        .unwrap();
+        let lines: Vec<Envelope<String>> = msg
+            .body
+            .split(|b| *b == b'\n')
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines[1].payload, "Yg==");
+
+        assert!(create_batch_message(
+            &[],
+            "127.0.0.1:9000",
+            &MetadataMode::None,
+            EnvelopeFormat::None,
+            BatchFormat::Lines,
+        )
+        .is_err());
+    }
+
+    /// Bindings for a stand-in component exporting the messaging handler
+    mod bench_bindings {
+        wit_bindgen_wrpc::generate!({
+            inline: "
+                package test:bench;
+
+                world bench {
+                    export wasmcloud:messaging/handler@0.2.0;
+                }
+            ",
+            path: "wit/deps/wasmcloud-messaging-0.2.0",
+            with: {
+                "wasmcloud:messaging/handler@0.2.0": generate,
+                "wasmcloud:messaging/types@0.2.0": crate::provider::bindings::wasmcloud::messaging0_2_0::types,
+            },
+        });
+    }
+
+    /// Bindings for a stand-in component exporting only the 0.3.0 incoming
+    /// handler, so reading a message goes back to the provider's `types`
+    mod v0_3_bindings {
+        wit_bindgen_wrpc::generate!({
+            inline: "
+                package test:incoming;
+
+                world incoming {
+                    export wasmcloud:messaging/incoming-handler@0.3.0;
+                }
+            ",
+            path: "wit/deps/wasmcloud-messaging-0.3.0",
+            generate_all,
+        });
+    }
+
+    use v0_3_bindings::exports::wasmcloud::messaging::incoming_handler as v0_3_handler;
+    use v0_3_bindings::wasmcloud::messaging::types as v0_3_types;
+    use wrpc_transport::tcp;
+
+    type ReadMessage = (Option<String>, Bytes, Option<Vec<(String, String)>>);
+
+    /// Reads each message it is handed back from the provider and reports it
+    #[derive(Clone)]
+    struct StandIn {
+        provider: Arc<tcp::Client<std::net::SocketAddr>>,
+        handled: mpsc::UnboundedSender<ReadMessage>,
+    }
+
+    impl<Ctx: Send> v0_3_handler::Handler<Ctx> for StandIn {
+        async fn handle(
+            &self,
+            _cx: Ctx,
+            message: ResourceOwn<v0_3_types::Message>,
+        ) -> anyhow::Result<Result<(), v0_3_types::Error>> {
+            let message = message.as_borrow();
+            let provider = self.provider.as_ref();
+            let topic = v0_3_types::Message::topic(provider, (), &message).await?;
+            let data = v0_3_types::Message::data(provider, (), &message).await?;
+            let metadata = v0_3_types::Message::metadata(provider, (), &message).await?;
+            self.handled.send((topic, data, metadata))?;
+            Ok(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_v0_3_delivery_sets_headers() {
+        use futures::StreamExt as _;
+        use wrpc_transport::frame::AcceptExt as _;
+        use wrpc_transport::Server;
+
+        // One server stands in for both the lattice-facing provider and the
+        // component; invocations carry no wasmCloud context
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(Server::default());
+        let provider = TcpUdpStreamProvider::default();
+        let (handled_tx, mut handled_rx) = mpsc::unbounded_channel();
+        let stand_in = StandIn {
+            provider: Arc::new(tcp::Client::from(addr)),
+            handled: handled_tx,
+        };
+        let mut invocations = bindings::serve(server.as_ref(), provider.clone())
+            .await
+            .unwrap();
+        invocations.extend(
+            v0_3_bindings::serve(server.as_ref(), stand_in)
+                .await
+                .unwrap(),
+        );
+        tokio::spawn(async move {
+            loop {
+                server
+                    .accept((&listener).map_context(|_| None::<SdkContext>))
+                    .await
+                    .unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            let mut invocations =
+                futures::stream::select_all(invocations.into_iter().map(|(_, _, s)| s));
+            while let Some(Ok(invocation)) = invocations.next().await {
+                tokio::spawn(invocation);
+            }
+        });
+
+        let mut frame = Frame::from(b"$GPGGA,1".to_vec());
+        frame.meta.peer = Some("10.0.0.5:4001".to_string());
+        frame.meta.protocol = "udp";
+        frame.meta.seq = 7;
+        let meta = frame.meta.clone();
+        let message = create_broker_message(
+            frame,
+            "10.0.0.5:4001",
+            &MetadataMode::None,
+            EnvelopeFormat::None,
+        )
+        .unwrap();
+        assert!(message_headers(MessagingVersion::V0_2, &message, &meta).is_empty());
+        let headers = message_headers(MessagingVersion::V0_3, &message, &meta);
+
+        let client = tcp::Client::from(addr);
+        invoke_handler(
+            &client,
+            &provider.messages,
+            MessagingVersion::V0_3,
+            &message,
+            &headers,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let (topic, body, received) = handled_rx.recv().await.unwrap();
+        assert_eq!(topic.as_deref(), Some("stream.10.0.0.5:4001"));
+        assert_eq!(body.as_ref(), b"$GPGGA,1");
+        let received = received.unwrap();
+        assert_eq!(received, headers);
+        assert!(received.contains(&("subject".to_string(), "stream.10.0.0.5:4001".to_string())));
+        assert!(received.contains(&("peer".to_string(), "10.0.0.5:4001".to_string())));
+        assert!(received.contains(&("seq".to_string(), "7".to_string())));
+    }
+
+    #[derive(Clone)]
+    struct AcceptAll;
+
+    impl<Ctx: Send> bench_bindings::exports::wasmcloud::messaging::handler::Handler<Ctx> for AcceptAll {
+        async fn handle_message(
+            &self,
+            _cx: Ctx,
+            _msg: types::BrokerMessage,
+        ) -> anyhow::Result<Result<(), String>> {
+            Ok(Ok(()))
+        }
+    }
+
+    /// Compare delivery throughput when a wRPC client is created per message
+    /// (as before client caching) with a single reused client.
+    ///
+    /// Requires a NATS server: `nats-server` or `NATS_URL=nats://host:4222`, then
+    /// `cargo test bench_wrpc_client_cache -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_wrpc_client_cache() {
+        use futures::StreamExt as _;
+
+        const MESSAGES: u32 = 2000;
+        const PREFIX: &str = "bench.tcp-udp-stream.component";
+
+        let url = std::env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".into());
+        let nats = Arc::new(async_nats::connect(&url).await.expect("connect to NATS"));
+
+        // Serve the stand-in component
+        let server_nats = Arc::clone(&nats);
+        tokio::spawn(async move {
+            let server = wrpc_transport_nats::Client::new(server_nats, PREFIX, None)
+                .await
+                .unwrap();
+            let invocations = bench_bindings::serve(&server, AcceptAll).await.unwrap();
+            let mut invocations =
+                futures::stream::select_all(invocations.into_iter().map(|(_, _, s)| s));
+            while let Some(Ok(invocation)) = invocations.next().await {
+                tokio::spawn(invocation);
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let message = types::BrokerMessage {
+            subject: "stream.127.0.0.1:9000".to_string(),
+            body: Bytes::from_static(b"$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M"),
+            reply_to: None,
+        };
+
+        let start = std::time::Instant::now();
+        for _ in 0..MESSAGES {
+            let client = wrpc_transport_nats::Client::new(Arc::clone(&nats), PREFIX, None)
+                .await
+                .unwrap();
+            handler::handle_message(&client, None, &message)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let uncached = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let client = wrpc_transport_nats::Client::new(Arc::clone(&nats), PREFIX, None)
+            .await
+            .unwrap();
+        for _ in 0..MESSAGES {
+            handler::handle_message(&client, None, &message)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let cached = start.elapsed();
+
+        let rate = |elapsed: std::time::Duration| f64::from(MESSAGES) / elapsed.as_secs_f64();
+        println!(
+            "client per message: {:.0} msg/s, cached client: {:.0} msg/s",
+            rate(uncached),
+            rate(cached)
+        );
+    }
 }
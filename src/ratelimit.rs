@@ -0,0 +1,213 @@
+//! Token-bucket rate limiting of inbound frames (`max_frames_per_sec` and
+//! `max_bytes_per_sec`).
+//!
+//! Each limit is a bucket that holds up to its burst size and refills at the
+//! configured rate. A frame costs one frame token and one byte token per
+//! byte; a frame larger than the byte burst costs a full bucket so it can
+//! still pass when the link has been quiet.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::config::{ConnectionConfig, RateLimitPolicy};
+
+/// Error returned by the message handler to close a connection that exceeded
+/// its rate limit under [`RateLimitPolicy::Disconnect`]
+#[derive(Debug)]
+pub struct RateLimitExceeded;
+
+impl fmt::Display for RateLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("rate limit exceeded")
+    }
+}
+
+impl std::error::Error for RateLimitExceeded {}
+
+/// What to do with a frame, as decided by [`RateLimiter::admit`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admission {
+    /// The frame is within the limits
+    Pass,
+    /// The frame is delivered, but reading pauses for this long
+    Delay(Duration),
+    /// The frame is dropped
+    Drop,
+    /// The connection is closed
+    Disconnect,
+}
+
+/// A single token bucket
+#[derive(Debug)]
+struct TokenBucket {
+    /// Tokens added per second
+    rate: f64,
+    /// Capacity of the bucket
+    burst: f64,
+    /// Tokens currently available; negative while a delayed frame is repaid
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket, or `None` if `rate` is 0 (unlimited)
+    fn new(rate: u64, burst: u64, now: Instant) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+        let burst = if burst == 0 { rate } else { burst } as f64;
+        Some(TokenBucket {
+            rate: rate as f64,
+            burst,
+            tokens: burst,
+            updated: now,
+        })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = self.updated.max(now);
+    }
+
+    /// Time until `cost` tokens are available
+    fn wait(&self, cost: f64) -> Duration {
+        let missing = cost.min(self.burst) - self.tokens;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.rate)
+        }
+    }
+
+    fn take(&mut self, cost: f64) {
+        self.tokens -= cost.min(self.burst);
+    }
+}
+
+/// Frame and byte rate limits of one link
+#[derive(Debug)]
+pub struct RateLimiter {
+    frames: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    policy: RateLimitPolicy,
+    /// When reading may continue after a delayed frame
+    resume_at: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Build the limiter for a link, or `None` if no rate limit is set
+    pub fn from_config(config: &ConnectionConfig, now: Instant) -> Option<Self> {
+        let frames = TokenBucket::new(config.max_frames_per_sec, config.rate_burst_frames, now);
+        let bytes = TokenBucket::new(config.max_bytes_per_sec, config.rate_burst_bytes, now);
+        if frames.is_none() && bytes.is_none() {
+            return None;
+        }
+        Some(RateLimiter {
+            frames,
+            bytes,
+            policy: config.rate_limit_policy,
+            resume_at: None,
+        })
+    }
+
+    /// Charge a frame of `len` bytes against the limits
+    pub fn admit(&mut self, len: usize, now: Instant) -> Admission {
+        let mut wait = Duration::ZERO;
+        for (bucket, cost) in [(&mut self.frames, 1.0), (&mut self.bytes, len as f64)] {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                wait = wait.max(bucket.wait(cost));
+            }
+        }
+        if !wait.is_zero() {
+            match self.policy {
+                RateLimitPolicy::Drop => return Admission::Drop,
+                RateLimitPolicy::Disconnect => return Admission::Disconnect,
+                RateLimitPolicy::Delay => self.resume_at = Some(now + wait),
+            }
+        }
+        if let Some(bucket) = &mut self.frames {
+            bucket.take(1.0);
+        }
+        if let Some(bucket) = &mut self.bytes {
+            bucket.take(len as f64);
+        }
+        if wait.is_zero() {
+            Admission::Pass
+        } else {
+            Admission::Delay(wait)
+        }
+    }
+
+    /// When reading may continue, if a delayed frame is still being repaid
+    pub fn resume_at(&self) -> Option<Instant> {
+        self.resume_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limiter(frames: u64, bytes: u64, burst: u64, policy: RateLimitPolicy) -> RateLimiter {
+        RateLimiter::from_config(
+            &ConnectionConfig {
+                max_frames_per_sec: frames,
+                max_bytes_per_sec: bytes,
+                rate_burst_frames: burst,
+                rate_limit_policy: policy,
+                ..Default::default()
+            },
+            Instant::now(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_frame_burst_and_refill() {
+        assert!(RateLimiter::from_config(&ConnectionConfig::default(), Instant::now()).is_none());
+
+        let mut limiter = rate_limiter(10, 0, 3, RateLimitPolicy::Drop);
+        let start = limiter.frames.as_ref().unwrap().updated;
+        for _ in 0..3 {
+            assert_eq!(limiter.admit(100, start), Admission::Pass);
+        }
+        assert_eq!(limiter.admit(100, start), Admission::Drop);
+        // One token back after 100ms at 10 frames/s
+        let later = start + Duration::from_millis(100);
+        assert_eq!(limiter.admit(100, later), Admission::Pass);
+        assert_eq!(limiter.admit(100, later), Admission::Drop);
+        // Refill never exceeds the burst
+        let idle = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.admit(100, idle), Admission::Pass);
+        }
+        assert_eq!(limiter.admit(100, idle), Admission::Drop);
+    }
+
+    #[test]
+    fn test_byte_limit_policies() {
+        let mut limiter = rate_limiter(0, 1000, 0, RateLimitPolicy::Delay);
+        let start = limiter.bytes.as_ref().unwrap().updated;
+        assert_eq!(limiter.admit(800, start), Admission::Pass);
+        assert_eq!(limiter.resume_at(), None);
+        // 400 bytes needs 200 more tokens: 200ms at 1000 bytes/s
+        assert_eq!(
+            limiter.admit(400, start),
+            Admission::Delay(Duration::from_millis(200))
+        );
+        assert_eq!(
+            limiter.resume_at(),
+            Some(start + Duration::from_millis(200))
+        );
+        // A frame larger than the burst costs a full bucket
+        let idle = start + Duration::from_secs(5);
+        assert_eq!(limiter.admit(5000, idle), Admission::Pass);
+
+        let mut limiter = rate_limiter(0, 1000, 0, RateLimitPolicy::Disconnect);
+        let start = limiter.bytes.as_ref().unwrap().updated;
+        assert_eq!(limiter.admit(1000, start), Admission::Pass);
+        assert_eq!(limiter.admit(1, start), Admission::Disconnect);
+    }
+}
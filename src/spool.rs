@@ -0,0 +1,657 @@
+//! Write-ahead spool of messages the linked component could not receive.
+//!
+//! Messages are appended to numbered segment files in the link's spool
+//! directory and read back in order. Each record is
+//! `len: u32 | crc32: u32 | spooled_at_ms: u64 | subject_len: u32 |
+//! headers_len: u32 | subject | headers | body`, all integers big-endian,
+//! where `len` and the checksum cover everything after the checksum and
+//! `headers` is a sequence of `key_len: u32 | key | value_len: u32 | value`.
+//! A `cursor` file records the segment and offset of the next record to
+//! replay, so a restarted provider resumes where it stopped. Each record is
+//! synced to disk before [`Spool::append`] returns.
+//!
+//! The oldest segments are removed when the spool grows beyond
+//! `spool_max_bytes`, and records older than `spool_max_age_secs` are skipped.
+//! A record cut short by a crash is dropped, along with anything after it in
+//! its segment.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::config::ConnectionConfig;
+
+/// Segment file extension
+const SEGMENT_EXT: &str = "seg";
+
+/// Name of the replay cursor file
+const CURSOR_FILE: &str = "cursor";
+
+/// Size of the record header: length and checksum
+const HEADER_LEN: u64 = 8;
+
+/// Size of the fixed part of a record after the header
+const FIXED_LEN: usize = 16;
+
+/// A message read back from the spool
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpooledMessage {
+    pub subject: String,
+    /// Headers sent to `wasmcloud:messaging@0.3.0` components
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// When the message was spooled, in milliseconds since the Unix epoch
+    pub spooled_at_ms: u64,
+}
+
+/// Size of the spool and what passed through it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SpoolStatus {
+    /// Messages waiting to be replayed
+    pub messages: u64,
+    /// Bytes of spooled records waiting to be replayed
+    pub bytes: u64,
+    /// Segment files on disk
+    pub segments: usize,
+    /// Messages appended since the spool was opened
+    pub spooled: u64,
+    /// Messages replayed since the spool was opened
+    pub replayed: u64,
+    /// Messages dropped by the size or age caps since the spool was opened
+    pub dropped: u64,
+}
+
+#[derive(Debug)]
+struct Segment {
+    id: u64,
+    len: u64,
+    records: u64,
+    /// Spool time of the newest record, in milliseconds since the Unix epoch
+    newest_ms: u64,
+}
+
+/// Append-only segment files with a replay cursor
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_age: Option<Duration>,
+    segment_bytes: u64,
+    /// Oldest first; the last segment is the one being appended to. An
+    /// empty spool has no segments.
+    segments: VecDeque<Segment>,
+    writer: Option<File>,
+    /// Id of the next segment; ids only increase so a stale cursor never
+    /// points into a newer segment
+    next_id: u64,
+    /// Offset of the next record in the oldest segment
+    read_offset: u64,
+    /// Records of the oldest segment already replayed
+    read_records: u64,
+    /// Length of the record returned by the last [`Spool::peek`]
+    peeked: Option<u64>,
+    status: SpoolStatus,
+}
+
+impl Spool {
+    /// Open or create the spool in `dir`, recovering its segments and cursor
+    pub fn open(dir: &Path, config: &ConnectionConfig) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some(SEGMENT_EXT) {
+                if let Some(id) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort_unstable();
+        let (cursor_id, cursor_offset) = read_cursor(dir);
+
+        let mut spool = Spool {
+            dir: dir.to_path_buf(),
+            max_bytes: config.spool_max_bytes,
+            max_age: (config.spool_max_age_secs > 0)
+                .then(|| Duration::from_secs(config.spool_max_age_secs)),
+            segment_bytes: config.spool_segment_bytes.max(1),
+            segments: VecDeque::new(),
+            writer: None,
+            next_id: ids.last().copied().unwrap_or(0).max(cursor_id) + 1,
+            read_offset: 0,
+            read_records: 0,
+            peeked: None,
+            status: SpoolStatus::default(),
+        };
+        for id in ids {
+            let segment = spool.recover_segment(id)?;
+            spool.segments.push_back(segment);
+        }
+
+        while spool
+            .segments
+            .front()
+            .is_some_and(|segment| segment.id < cursor_id)
+        {
+            spool.remove_front()?;
+        }
+        if let Some(front) = spool.segments.front() {
+            if front.id == cursor_id {
+                spool.skip_to(cursor_offset.min(front.len))?;
+            }
+        }
+        if spool.pending() == 0 {
+            spool.clear()?;
+        } else if let Some(back) = spool.segments.back() {
+            spool.writer = Some(OpenOptions::new().append(true).open(spool.path(back.id))?);
+        }
+        Ok(spool)
+    }
+
+    /// Number of messages waiting to be replayed
+    pub fn pending(&self) -> u64 {
+        self.segments.iter().map(|s| s.records).sum::<u64>() - self.read_records
+    }
+
+    /// Current size and counters
+    pub fn status(&self) -> SpoolStatus {
+        SpoolStatus {
+            messages: self.pending(),
+            bytes: self.disk_bytes() - self.read_offset,
+            segments: self.segments.len(),
+            ..self.status.clone()
+        }
+    }
+
+    /// Append a message, removing the oldest segments if the spool would
+    /// exceed its size cap
+    pub fn append(
+        &mut self,
+        subject: &str,
+        headers: &[(String, String)],
+        body: &[u8],
+        now: SystemTime,
+    ) -> io::Result<()> {
+        let spooled_at_ms = unix_millis(now);
+        let mut encoded_headers = Vec::new();
+        for (key, value) in headers {
+            encoded_headers.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            encoded_headers.extend_from_slice(key.as_bytes());
+            encoded_headers.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            encoded_headers.extend_from_slice(value.as_bytes());
+        }
+        let mut payload =
+            Vec::with_capacity(FIXED_LEN + subject.len() + encoded_headers.len() + body.len());
+        payload.extend_from_slice(&spooled_at_ms.to_be_bytes());
+        payload.extend_from_slice(&(subject.len() as u32).to_be_bytes());
+        payload.extend_from_slice(&(encoded_headers.len() as u32).to_be_bytes());
+        payload.extend_from_slice(subject.as_bytes());
+        payload.extend_from_slice(&encoded_headers);
+        payload.extend_from_slice(body);
+        let record_len = HEADER_LEN + payload.len() as u64;
+        if record_len > self.max_bytes || payload.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("message of {record_len} bytes exceeds spool_max_bytes"),
+            ));
+        }
+
+        self.expire(now)?;
+        while !self.segments.is_empty() && self.disk_bytes() + record_len > self.max_bytes {
+            let dropped = self.segments[0].records - self.read_records;
+            warn!(dropped, dir = %self.dir.display(), "spool full, dropping oldest segment");
+            self.status.dropped += dropped;
+            self.remove_front()?;
+        }
+        if self
+            .segments
+            .back()
+            .is_none_or(|back| back.len > 0 && back.len + record_len > self.segment_bytes)
+        {
+            self.rotate()?;
+        }
+
+        let mut record = Vec::with_capacity(record_len as usize);
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(&crc32fast::hash(&payload).to_be_bytes());
+        record.extend_from_slice(&payload);
+        let writer = self.writer.as_mut().expect("spool writer is open");
+        writer.write_all(&record)?;
+        writer.sync_data()?;
+        let back = self.segments.back_mut().expect("spool has a segment");
+        back.len += record_len;
+        back.records += 1;
+        back.newest_ms = back.newest_ms.max(spooled_at_ms);
+        self.status.spooled += 1;
+        Ok(())
+    }
+
+    /// The next message to replay, skipping any older than the age cap. Call
+    /// [`Spool::advance`] once it has been delivered.
+    pub fn peek(&mut self, now: SystemTime) -> io::Result<Option<SpooledMessage>> {
+        self.expire(now)?;
+        let cutoff = self
+            .max_age
+            .map(|age| unix_millis(now).saturating_sub(age.as_millis() as u64));
+        loop {
+            let Some(front) = self.segments.front() else {
+                return Ok(None);
+            };
+            if self.read_offset >= front.len {
+                if self.segments.len() == 1 {
+                    return Ok(None);
+                }
+                self.remove_front()?;
+                continue;
+            }
+            let (len, message) = self.read_record(front.id, self.read_offset)?;
+            if cutoff.is_some_and(|cutoff| message.spooled_at_ms < cutoff) {
+                self.status.dropped += 1;
+                self.consume(len)?;
+                continue;
+            }
+            self.peeked = Some(len);
+            return Ok(Some(message));
+        }
+    }
+
+    /// Move past the message returned by the last [`Spool::peek`]
+    pub fn advance(&mut self) -> io::Result<()> {
+        if let Some(len) = self.peeked.take() {
+            self.status.replayed += 1;
+            self.consume(len)?;
+        }
+        Ok(())
+    }
+
+    fn disk_bytes(&self) -> u64 {
+        self.segments.iter().map(|s| s.len).sum()
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{id:020}.{SEGMENT_EXT}"))
+    }
+
+    /// Count the records of a segment file, truncating a damaged tail
+    fn recover_segment(&self, id: u64) -> io::Result<Segment> {
+        let path = self.path(id);
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let file_len = file.metadata()?.len();
+        let mut segment = Segment {
+            id,
+            len: 0,
+            records: 0,
+            newest_ms: 0,
+        };
+        while segment.len < file_len {
+            match read_record_at(&file, segment.len) {
+                Ok((len, message)) => {
+                    segment.len += len;
+                    segment.records += 1;
+                    segment.newest_ms = segment.newest_ms.max(message.spooled_at_ms);
+                }
+                Err(e) => {
+                    warn!(
+                        path = %path.display(),
+                        offset = segment.len,
+                        error = %e,
+                        "damaged spool record, truncating segment"
+                    );
+                    file.set_len(segment.len)?;
+                    break;
+                }
+            }
+        }
+        Ok(segment)
+    }
+
+    fn read_record(&self, id: u64, offset: u64) -> io::Result<(u64, SpooledMessage)> {
+        read_record_at(&File::open(self.path(id))?, offset)
+    }
+
+    /// Move the read position of the oldest segment to `offset`
+    fn skip_to(&mut self, offset: u64) -> io::Result<()> {
+        let id = self.segments[0].id;
+        while self.read_offset < offset {
+            let (len, _) = self.read_record(id, self.read_offset)?;
+            self.read_offset += len;
+            self.read_records += 1;
+        }
+        Ok(())
+    }
+
+    /// Mark a record of `len` bytes at the read position as replayed
+    fn consume(&mut self, len: u64) -> io::Result<()> {
+        self.read_offset += len;
+        self.read_records += 1;
+        if self.pending() == 0 {
+            return self.clear();
+        }
+        if self.segments.len() > 1 && self.read_offset >= self.segments[0].len {
+            self.remove_front()?;
+        }
+        self.write_cursor()
+    }
+
+    /// Remove whole segments whose newest record is older than the age cap
+    fn expire(&mut self, now: SystemTime) -> io::Result<()> {
+        let Some(max_age) = self.max_age else {
+            return Ok(());
+        };
+        let cutoff = unix_millis(now).saturating_sub(max_age.as_millis() as u64);
+        while self
+            .segments
+            .front()
+            .is_some_and(|front| front.records > 0 && front.newest_ms < cutoff)
+        {
+            self.status.dropped += self.segments[0].records - self.read_records;
+            self.remove_front()?;
+        }
+        Ok(())
+    }
+
+    /// Start a new segment for appending
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.sync_data()?;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(id))?;
+        self.segments.push_back(Segment {
+            id,
+            len: 0,
+            records: 0,
+            newest_ms: 0,
+        });
+        self.writer = Some(file);
+        if self.segments.len() == 1 {
+            self.write_cursor()?;
+        }
+        Ok(())
+    }
+
+    /// Delete the oldest segment and start reading the next one
+    fn remove_front(&mut self) -> io::Result<()> {
+        if let Some(front) = self.segments.pop_front() {
+            remove_if_exists(&self.path(front.id))?;
+        }
+        if self.segments.is_empty() {
+            self.writer = None;
+        }
+        self.read_offset = 0;
+        self.read_records = 0;
+        self.peeked = None;
+        self.write_cursor()
+    }
+
+    /// Delete every segment once all messages are replayed or dropped
+    fn clear(&mut self) -> io::Result<()> {
+        while !self.segments.is_empty() {
+            self.remove_front()?;
+        }
+        Ok(())
+    }
+
+    /// Record the read position, replacing the cursor file atomically
+    fn write_cursor(&self) -> io::Result<()> {
+        let (id, offset) = self
+            .segments
+            .front()
+            .map_or((0, 0), |front| (front.id, self.read_offset));
+        let mut data = [0u8; 16];
+        data[..8].copy_from_slice(&id.to_be_bytes());
+        data[8..].copy_from_slice(&offset.to_be_bytes());
+        let tmp = self.dir.join(format!("{CURSOR_FILE}.tmp"));
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, self.dir.join(CURSOR_FILE))
+    }
+}
+
+/// Read the segment id and offset saved in the cursor file, or zeros if
+/// there is none
+fn read_cursor(dir: &Path) -> (u64, u64) {
+    match fs::read(dir.join(CURSOR_FILE)) {
+        Ok(data) if data.len() == 16 => (
+            u64::from_be_bytes(data[..8].try_into().expect("8 bytes")),
+            u64::from_be_bytes(data[8..].try_into().expect("8 bytes")),
+        ),
+        _ => (0, 0),
+    }
+}
+
+/// Read the record at `offset`, returning its total length. A length
+/// running past the end of the file is rejected before anything is
+/// allocated for it.
+fn read_record_at(file: &File, offset: u64) -> io::Result<(u64, SpooledMessage)> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    file.read_exact_at(&mut header, offset)?;
+    let len = u32::from_be_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+    let crc = u32::from_be_bytes(header[4..].try_into().expect("4 bytes"));
+    let remaining = file.metadata()?.len().saturating_sub(offset + HEADER_LEN);
+    if len as u64 > remaining {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "spool record length runs past the end of the segment",
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    file.read_exact_at(&mut payload, offset + HEADER_LEN)?;
+    if len < FIXED_LEN || crc32fast::hash(&payload) != crc {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "spool record checksum mismatch",
+        ));
+    }
+    let spooled_at_ms = u64::from_be_bytes(payload[..8].try_into().expect("8 bytes"));
+    let subject_len = u32::from_be_bytes(payload[8..12].try_into().expect("4 bytes")) as usize;
+    let headers_len = u32::from_be_bytes(payload[12..16].try_into().expect("4 bytes")) as usize;
+    let overrun = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "spool record fields overrun record",
+        )
+    };
+    let headers_start = FIXED_LEN + subject_len;
+    let body_start = headers_start + headers_len;
+    let subject = payload.get(FIXED_LEN..headers_start).ok_or_else(overrun)?;
+    let mut encoded_headers = payload.get(headers_start..body_start).ok_or_else(overrun)?;
+    let mut headers = Vec::new();
+    while !encoded_headers.is_empty() {
+        let key = take_field(&mut encoded_headers).ok_or_else(overrun)?;
+        let value = take_field(&mut encoded_headers).ok_or_else(overrun)?;
+        headers.push((
+            String::from_utf8_lossy(key).into_owned(),
+            String::from_utf8_lossy(value).into_owned(),
+        ));
+    }
+    Ok((
+        HEADER_LEN + len as u64,
+        SpooledMessage {
+            subject: String::from_utf8_lossy(subject).into_owned(),
+            headers,
+            body: payload[body_start..].to_vec(),
+            spooled_at_ms,
+        },
+    ))
+}
+
+/// Split a `len: u32 | bytes` field off the front of `data`
+fn take_field<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let field = data.get(4..4 + len)?;
+    *data = &data[4 + len..];
+    Some(field)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spool_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tcp-udp-spool-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config(max_bytes: u64, segment_bytes: u64) -> ConnectionConfig {
+        ConnectionConfig {
+            spool_max_bytes: max_bytes,
+            spool_segment_bytes: segment_bytes,
+            ..Default::default()
+        }
+    }
+
+    fn replay(spool: &mut Spool, now: SystemTime) -> Vec<String> {
+        let mut bodies = Vec::new();
+        while let Some(message) = spool.peek(now).unwrap() {
+            bodies.push(String::from_utf8(message.body).unwrap());
+            spool.advance().unwrap();
+        }
+        bodies
+    }
+
+    #[test]
+    fn test_replay_in_order_across_restart() {
+        let dir = spool_dir("restart");
+        let now = SystemTime::now();
+        let mut spool = Spool::open(&dir, &config(1 << 20, 64)).unwrap();
+        let headers = [("peer".to_string(), "10.0.0.5:4001".to_string())];
+        spool.append("sensor.a", &headers, b"frame 0", now).unwrap();
+        for i in 1..10 {
+            spool
+                .append("sensor.a", &[], format!("frame {i}").as_bytes(), now)
+                .unwrap();
+        }
+        let status = spool.status();
+        assert_eq!(status.messages, 10);
+        assert!(status.segments > 1);
+
+        let message = spool.peek(now).unwrap().unwrap();
+        assert_eq!(message.subject, "sensor.a");
+        assert_eq!(message.headers, headers);
+        assert_eq!(message.body, b"frame 0");
+        spool.advance().unwrap();
+        spool.peek(now).unwrap();
+        spool.advance().unwrap();
+        drop(spool);
+
+        // A restart resumes after the last replayed message
+        let mut spool = Spool::open(&dir, &config(1 << 20, 64)).unwrap();
+        assert_eq!(spool.status().messages, 8);
+        spool.append("sensor.a", &[], b"frame 10", now).unwrap();
+        let bodies = replay(&mut spool, now);
+        assert_eq!(bodies.first().map(String::as_str), Some("frame 2"));
+        assert_eq!(bodies.last().map(String::as_str), Some("frame 10"));
+        assert_eq!(bodies.len(), 9);
+        assert_eq!(spool.pending(), 0);
+        assert_eq!(spool.status().segments, 0);
+        assert_eq!(spool.status().replayed, 9);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_size_and_age_caps() {
+        let dir = spool_dir("caps");
+        let now = SystemTime::now();
+        // Each record is 8 + 16 + 1 + 7 = 32 bytes; two fit in a segment
+        let mut spool = Spool::open(&dir, &config(128, 64)).unwrap();
+        for i in 0..6 {
+            spool
+                .append("s", &[], format!("frame {i}").as_bytes(), now)
+                .unwrap();
+        }
+        let status = spool.status();
+        assert_eq!(status.messages, 4);
+        assert_eq!(status.dropped, 2);
+        assert!(status.bytes <= 128);
+        assert_eq!(
+            replay(&mut spool, now),
+            ["frame 2", "frame 3", "frame 4", "frame 5"]
+        );
+        assert!(spool.append("s", &[], &[0; 200], now).is_err());
+
+        let aged = ConnectionConfig {
+            spool_max_age_secs: 60,
+            ..config(1 << 20, 1 << 20)
+        };
+        let mut spool = Spool::open(&dir, &aged).unwrap();
+        spool
+            .append("s", &[], b"old", now - Duration::from_secs(120))
+            .unwrap();
+        spool.append("s", &[], b"new", now).unwrap();
+        assert_eq!(replay(&mut spool, now), ["new"]);
+        assert_eq!(spool.status().dropped, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_tail_is_truncated() {
+        let dir = spool_dir("torn");
+        let now = SystemTime::now();
+        let mut spool = Spool::open(&dir, &config(1 << 20, 1 << 20)).unwrap();
+        spool.append("s", &[], b"complete", now).unwrap();
+        spool.append("s", &[], b"torn", now).unwrap();
+        let path = spool.path(spool.segments[0].id);
+        drop(spool);
+
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 2)
+            .unwrap();
+        let mut spool = Spool::open(&dir, &config(1 << 20, 1 << 20)).unwrap();
+        assert_eq!(spool.status().messages, 1);
+        spool.append("s", &[], b"after", now).unwrap();
+        assert_eq!(replay(&mut spool, now), ["complete", "after"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_length_is_rejected() {
+        let dir = spool_dir("length");
+        let now = SystemTime::now();
+        let mut spool = Spool::open(&dir, &config(1 << 20, 1 << 20)).unwrap();
+        spool.append("s", &[], b"complete", now).unwrap();
+        let path = spool.path(spool.segments[0].id);
+        drop(spool);
+
+        let offset = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        file.write_all(&[0; 64]).unwrap();
+        let err = read_record_at(&File::open(&path).unwrap(), offset).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut spool = Spool::open(&dir, &config(1 << 20, 1 << 20)).unwrap();
+        assert_eq!(replay(&mut spool, now), ["complete"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
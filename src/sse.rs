@@ -0,0 +1,118 @@
+//! Incremental parser for the `text/event-stream` (Server-Sent Events) format.
+//!
+//! Lines are fed one at a time; a blank line dispatches the accumulated event.
+//! Field handling follows the WHATWG HTML "event stream interpretation" rules.
+
+/// A dispatched Server-Sent Event
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    /// Event type from the `event:` field, `None` for the default "message" type
+    pub event: Option<String>,
+    /// Event payload, `data:` lines joined with `\n`
+    pub data: String,
+}
+
+/// Parser state carried across lines and reconnects
+#[derive(Debug, Default)]
+pub struct SseParser {
+    event: String,
+    data: String,
+    /// Last event ID seen, sent as `Last-Event-ID` when resuming
+    pub last_event_id: Option<String>,
+    /// Reconnection delay requested by the server via `retry:`
+    pub retry_ms: Option<u64>,
+}
+
+impl SseParser {
+    /// Feed a single line (without its terminator). Returns an event when the
+    /// line is blank and data has been accumulated.
+    pub fn push_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(ms) = value.parse() {
+                    self.retry_ms = Some(ms);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Drop any partially received event, e.g. after the connection is lost
+    pub fn reset_event(&mut self) {
+        self.event.clear();
+        self.data.clear();
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = std::mem::take(&mut self.event);
+        let mut data = std::mem::take(&mut self.data);
+        if data.is_empty() {
+            return None;
+        }
+        data.pop();
+        Some(SseEvent {
+            event: (!event.is_empty() && event != "message").then_some(event),
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(parser: &mut SseParser, text: &str) -> Vec<SseEvent> {
+        text.split('\n')
+            .filter_map(|line| parser.push_line(line))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let mut parser = SseParser::default();
+        let events = feed(
+            &mut parser,
+            ": keep-alive\nevent: position\nid: 42\ndata: {\"lat\":1}\n\ndata: a\ndata:b\n\n",
+        );
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: Some("position".to_string()),
+                    data: "{\"lat\":1}".to_string(),
+                },
+                SseEvent {
+                    event: None,
+                    data: "a\nb".to_string(),
+                },
+            ]
+        );
+        assert_eq!(parser.last_event_id.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn test_retry_and_empty_events() {
+        let mut parser = SseParser::default();
+        let events = feed(&mut parser, "retry: 1500\nevent: ignored\n\n");
+        assert!(events.is_empty());
+        assert_eq!(parser.retry_ms, Some(1500));
+    }
+}
@@ -0,0 +1,70 @@
+//! Per-link counters of frames and peers the provider cut short, dropped,
+//! held back or refused.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+/// Counters shared by a link's stream client and the provider
+#[derive(Debug, Default)]
+pub struct LinkStats {
+    /// Frames cut to `max_frame_bytes`
+    pub frames_truncated: AtomicU64,
+    /// Frames dropped for exceeding `max_frame_bytes`
+    pub frames_discarded: AtomicU64,
+    /// Connections closed because a frame exceeded `max_frame_bytes`
+    pub oversize_disconnects: AtomicU64,
+    /// Frames dropped for exceeding the link's rate limits
+    pub frames_rate_dropped: AtomicU64,
+    /// Bytes in frames dropped for exceeding the link's rate limits
+    pub bytes_rate_dropped: AtomicU64,
+    /// Frames after which reading paused to stay within the rate limits
+    pub frames_rate_delayed: AtomicU64,
+    /// Connections closed for exceeding the link's rate limits
+    pub rate_limit_disconnects: AtomicU64,
+    /// Datagrams and TCP clients refused by `allow_cidrs`/`deny_cidrs`
+    pub sources_rejected: AtomicU64,
+    /// TCP clients refused by `max_clients` or a rate limit cooldown
+    pub clients_refused: AtomicU64,
+}
+
+/// Point-in-time copy of [`LinkStats`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LinkStatsSnapshot {
+    pub frames_truncated: u64,
+    pub frames_discarded: u64,
+    pub oversize_disconnects: u64,
+    pub frames_rate_dropped: u64,
+    pub bytes_rate_dropped: u64,
+    pub frames_rate_delayed: u64,
+    pub rate_limit_disconnects: u64,
+    pub sources_rejected: u64,
+    pub clients_refused: u64,
+}
+
+impl LinkStats {
+    /// Increment a counter by one
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increase a counter by `n`
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Read every counter
+    pub fn snapshot(&self) -> LinkStatsSnapshot {
+        LinkStatsSnapshot {
+            frames_truncated: self.frames_truncated.load(Ordering::Relaxed),
+            frames_discarded: self.frames_discarded.load(Ordering::Relaxed),
+            oversize_disconnects: self.oversize_disconnects.load(Ordering::Relaxed),
+            frames_rate_dropped: self.frames_rate_dropped.load(Ordering::Relaxed),
+            bytes_rate_dropped: self.bytes_rate_dropped.load(Ordering::Relaxed),
+            frames_rate_delayed: self.frames_rate_delayed.load(Ordering::Relaxed),
+            rate_limit_disconnects: self.rate_limit_disconnects.load(Ordering::Relaxed),
+            sources_rejected: self.sources_rejected.load(Ordering::Relaxed),
+            clients_refused: self.clients_refused.load(Ordering::Relaxed),
+        }
+    }
+}
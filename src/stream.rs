@@ -1,76 +1,619 @@
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::net::{TcpStream, UdpSocket};
-use tracing::{debug, error, info};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::config::{ConnectionConfig, StreamProtocol};
+use anyhow::Context as _;
+use futures::stream::FuturesUnordered;
+use futures::{SinkExt, StreamExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket, UnixDatagram, UnixStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, warn};
+
+use crate::capture::{CaptureReader, CaptureThread};
+use crate::charset::Transcoder;
+use crate::config::{
+    Compression, ConnectionConfig, DecoderKind, DecompressMode, FileStart, OversizePolicy,
+    StreamProtocol, TcpMode, UdpMode,
+};
+use crate::decompress::Decompressor;
+use crate::proxy::{self, Proxy, ProxyKind, RelayTarget, SocksAddr, UdpAssociation};
+use crate::ratelimit::{Admission, RateLimitExceeded, RateLimiter};
+use crate::sse::SseParser;
+use crate::stats::LinkStats;
+use crate::tail::{Checkpoint, FileTail};
+use crate::telnet::{ComPortSettings, TelnetFilter, TelnetStream};
+
+/// Reconnection delay for SSE streams until the server sends `retry:`
+const DEFAULT_SSE_RETRY_MS: u64 = 3000;
+
+/// Longest RFC 6587 `MSG-LEN` read, including the trailing space
+const MAX_OCTET_COUNT_DIGITS: u64 = 21;
+
+/// Minimum interval between repeated warnings about rejected peers
+const REJECTION_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Frames listen-mode clients may queue for the listener before their reads
+/// pause
+const LISTEN_QUEUE_LEN: usize = 1024;
+
+/// Source of process-unique connection ids
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Reference point for monotonic receive timestamps
+static MONOTONIC_ORIGIN: OnceLock<Instant> = OnceLock::new();
+
+/// The instant monotonic receive timestamps are measured from, fixed on first use
+pub fn monotonic_origin() -> Instant {
+    *MONOTONIC_ORIGIN.get_or_init(Instant::now)
+}
+
+/// A single message received from a stream
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frame {
+    /// Raw message bytes
+    pub data: Vec<u8>,
+    /// Subject supplied by the transport itself (e.g. an SSE event type),
+    /// overriding the default per-connection subject
+    pub subject: Option<String>,
+    /// Where and when the frame was received
+    pub meta: FrameMetadata,
+}
+
+/// Per-frame receive metadata, stamped by [`StreamClient::run`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameMetadata {
+    /// Address of the sender: the datagram source for UDP, the connected peer
+    /// for streams, or the configured path/URL when no socket address exists
+    pub peer: Option<String>,
+    /// Local socket address the frame was received on, when known
+    pub local: Option<String>,
+    /// Config name of the protocol the frame was received over
+    pub protocol: &'static str,
+    /// Wall-clock time the frame was handed to the message handler
+    pub received_at: Option<SystemTime>,
+    /// Monotonic time the frame was handed to the message handler
+    pub received_mono: Option<Instant>,
+    /// Sequence number of the frame on its connection, starting at 1 and
+    /// restarting with each connection generation
+    pub seq: u64,
+    /// Process-unique id of the stream client the frame arrived on
+    pub connection_id: u64,
+    /// Number of the (re)connection within the stream client, or of the
+    /// accepted client in TCP listen mode, starting at 1
+    pub generation: u64,
+}
+
+impl From<Vec<u8>> for Frame {
+    fn from(data: Vec<u8>) -> Self {
+        Frame {
+            data,
+            ..Default::default()
+        }
+    }
+}
+
+impl Frame {
+    /// Record the sender and local addresses of this frame
+    fn with_addrs(mut self, peer: Option<&str>, local: Option<&str>) -> Self {
+        self.meta.peer = peer.map(str::to_string);
+        self.meta.local = local.map(str::to_string);
+        self
+    }
+}
 
 /// TCP/UDP stream client handler
 pub struct StreamClient {
     config: ConnectionConfig,
+    stats: Arc<LinkStats>,
+    rate_limiter: Option<Mutex<RateLimiter>>,
 }
 
 impl StreamClient {
     /// Create a new stream client
     pub fn new(config: ConnectionConfig) -> Self {
-        Self { config }
+        Self {
+            rate_limiter: RateLimiter::from_config(&config, Instant::now()).map(Mutex::new),
+            config,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Counters of frames this client truncated, dropped or delayed
+    pub fn stats(&self) -> Arc<LinkStats> {
+        self.stats.clone()
     }
 
     /// Connect to the remote server and start receiving messages.
     ///
-    /// Calls `message_handler` for each received line (TCP) or datagram (UDP).
+    /// Calls `message_handler` for each received line (TCP, Unix stream) or
+    /// datagram (UDP, Unix datagram), WebSocket message (ws, wss), NDJSON line
+    /// (http-stream), Server-Sent Event (sse), captured frame (replay), line
+    /// appended to a file (file) or line from a serial port (rfc2217).
+    /// Reading waits while the handler does, so it can apply backpressure.
     /// The `shutdown_rx` is used to signal the client to stop reading.
+    ///
+    /// With `capture_path` set, every frame is also appended to the capture
+    /// file as it was received, before rate limiting.
+    ///
+    /// A connection closed for exceeding the link's rate limits is
+    /// re-established after `rate_limit_cooldown_ms`, as the next generation.
     pub async fn run<F>(
         &self,
-        mut message_handler: F,
+        message_handler: F,
         mut shutdown_rx: tokio::sync::oneshot::Receiver<()>,
     ) -> anyhow::Result<()>
     where
-        F: FnMut(Vec<u8>) -> anyhow::Result<()> + Send,
+        F: FrameSink + Send,
     {
-        match self.config.protocol {
-            StreamProtocol::Tcp => self.run_tcp(&mut message_handler, &mut shutdown_rx).await,
-            StreamProtocol::Udp => self.run_udp(&mut message_handler, &mut shutdown_rx).await,
+        self.check_decompress()?;
+        self.check_proxy()?;
+        self.check_broadcast()?;
+        Transcoder::from_config(&self.config)?;
+        let capture = CaptureThread::from_config(&self.config)
+            .with_context(|| format!("failed to open capture file {}", self.config.capture_path))?;
+
+        let mut handler = LinkFrames {
+            client: self,
+            sink: message_handler,
+            capture,
+            default_peer: self.config.addr(),
+            connection_id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            origin: monotonic_origin(),
+            generation: 0,
+            seq: 0,
+            base_generation: 0,
+        };
+
+        let result = loop {
+            let result = match self.config.protocol {
+                StreamProtocol::Tcp if self.config.tcp_mode == TcpMode::Listen => {
+                    self.run_tcp_listen(&mut handler, &mut shutdown_rx).await
+                }
+                StreamProtocol::Tcp => self.run_tcp(&mut handler, &mut shutdown_rx).await,
+                StreamProtocol::Udp => self.run_udp(&mut handler, &mut shutdown_rx).await,
+                StreamProtocol::Unix => self.run_unix(&mut handler, &mut shutdown_rx).await,
+                StreamProtocol::Unixgram => self.run_unixgram(&mut handler, &mut shutdown_rx).await,
+                StreamProtocol::Ws | StreamProtocol::Wss => {
+                    self.run_websocket(&mut handler, &mut shutdown_rx).await
+                }
+                StreamProtocol::HttpStream => {
+                    self.run_http_stream(&mut handler, &mut shutdown_rx).await
+                }
+                StreamProtocol::Sse => self.run_sse(&mut handler, &mut shutdown_rx).await,
+                StreamProtocol::Replay => self.run_replay(&mut handler, &mut shutdown_rx).await,
+                StreamProtocol::File => self.run_file(&mut handler, &mut shutdown_rx).await,
+                StreamProtocol::Rfc2217 => self.run_rfc2217(&mut handler, &mut shutdown_rx).await,
+            };
+            match result {
+                Err(e) if e.is::<RateLimitExceeded>() => {
+                    LinkStats::incr(&self.stats.rate_limit_disconnects);
+                }
+                result => break result,
+            }
+
+            let cooldown = Duration::from_millis(self.config.rate_limit_cooldown_ms);
+            warn!(
+                cooldown_ms = self.config.rate_limit_cooldown_ms,
+                "rate limit exceeded, disconnected"
+            );
+            tokio::select! {
+                _ = &mut shutdown_rx => {
+                    info!("shutdown signal received during rate limit cooldown");
+                    break Ok(());
+                }
+                _ = tokio::time::sleep(cooldown) => {}
+            }
+            handler.base_generation = handler.generation;
+        };
+
+        if let Some(capture) = handler.capture {
+            capture.close().await;
+        }
+        result
+    }
+
+    /// Charge a frame against the link's rate limits. Returns `false` if it
+    /// should be dropped, or a [`RateLimitExceeded`] error to disconnect.
+    fn admit(&self, len: usize) -> anyhow::Result<bool> {
+        let Some(limiter) = &self.rate_limiter else {
+            return Ok(true);
+        };
+        let admission = limiter
+            .lock()
+            .expect("rate limiter lock poisoned")
+            .admit(len, Instant::now());
+        match admission {
+            Admission::Pass => Ok(true),
+            Admission::Delay(wait) => {
+                debug!(len, wait = ?wait, "rate limit reached, pausing reads");
+                LinkStats::incr(&self.stats.frames_rate_delayed);
+                Ok(true)
+            }
+            Admission::Drop => {
+                debug!(len, "rate limit reached, dropping frame");
+                LinkStats::incr(&self.stats.frames_rate_dropped);
+                LinkStats::add(&self.stats.bytes_rate_dropped, len as u64);
+                Ok(false)
+            }
+            Admission::Disconnect => Err(RateLimitExceeded.into()),
+        }
+    }
+
+    /// Wait for `read` after any pause owed to the rate limits, so a delayed
+    /// link stops reading and the sender sees backpressure
+    async fn throttled<T>(&self, read: impl Future<Output = T>) -> T {
+        let resume_at = self.rate_limiter.as_ref().and_then(|limiter| {
+            limiter
+                .lock()
+                .expect("rate limiter lock poisoned")
+                .resume_at()
+        });
+        if let Some(resume_at) = resume_at {
+            tokio::time::sleep_until(resume_at.into()).await;
+        }
+        read.await
+    }
+
+    /// Message framing for stream transports
+    fn framing(&self) -> Framing {
+        match self.config.decoder {
+            DecoderKind::Syslog => Framing::OctetCounting,
+            _ => Framing::Lines,
+        }
+    }
+
+    /// Reject `decompress_mode` values the protocol cannot honour: `stream`
+    /// needs a byte stream, `frame` on a byte stream needs octet-counted
+    /// framing, since compressed data holds arbitrary `\n` bytes, and files
+    /// and replays are never decompressed
+    fn check_decompress(&self) -> anyhow::Result<()> {
+        if self.config.decompress == Compression::None {
+            return Ok(());
+        }
+        match (self.config.decompress_mode, &self.config.protocol) {
+            (
+                DecompressMode::Stream,
+                StreamProtocol::Tcp | StreamProtocol::Unix | StreamProtocol::Rfc2217,
+            ) => Ok(()),
+            (DecompressMode::Frame, StreamProtocol::File | StreamProtocol::Replay) => {
+                anyhow::bail!(
+                    "decompress_mode=frame is not supported for protocol {}",
+                    self.config.protocol.as_str()
+                )
+            }
+            (
+                DecompressMode::Frame,
+                StreamProtocol::Tcp | StreamProtocol::Unix | StreamProtocol::Rfc2217,
+            ) if self.framing() != Framing::OctetCounting => anyhow::bail!(
+                "decompress_mode=frame on a {} stream needs octet-counted framing \
+                 (decoder=syslog); use decompress_mode=stream",
+                self.config.protocol.as_str()
+            ),
+            (DecompressMode::Frame, _) => Ok(()),
+            (DecompressMode::Stream, protocol) => anyhow::bail!(
+                "decompress_mode=stream is not supported for protocol {}",
+                protocol.as_str()
+            ),
+        }
+    }
+
+    /// Reject a `proxy` the link cannot use: only outbound TCP, RFC 2217
+    /// and connected UDP links go through one, and UDP needs SOCKS5
+    fn check_proxy(&self) -> anyhow::Result<()> {
+        let Some(proxy) = Proxy::from_config(&self.config)? else {
+            return Ok(());
+        };
+        match &self.config.protocol {
+            StreamProtocol::Tcp if self.config.tcp_mode == TcpMode::Connect => Ok(()),
+            StreamProtocol::Rfc2217 => Ok(()),
+            StreamProtocol::Udp if self.config.udp_mode == UdpMode::Connected => {
+                if proxy.kind != ProxyKind::Socks5 {
+                    anyhow::bail!("UDP links need a socks5 proxy");
+                }
+                Ok(())
+            }
+            StreamProtocol::Tcp => anyhow::bail!("proxy is not supported with tcp_mode=listen"),
+            StreamProtocol::Udp => anyhow::bail!("proxy is not supported with udp_mode=broadcast"),
+            protocol => anyhow::bail!("proxy is not supported for protocol {}", protocol.as_str()),
+        }
+    }
+
+    /// Reject a loopback `host` in `udp_mode=broadcast`: a socket bound to
+    /// loopback never sees broadcasts, including with the default host
+    fn check_broadcast(&self) -> anyhow::Result<()> {
+        if self.config.protocol != StreamProtocol::Udp || self.config.udp_mode != UdpMode::Broadcast
+        {
+            return Ok(());
+        }
+        let host = self
+            .config
+            .host
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let loopback = host.eq_ignore_ascii_case("localhost")
+            || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        if loopback {
+            anyhow::bail!(
+                "udp_mode=broadcast cannot receive broadcasts on loopback host {}; \
+                 set host=0.0.0.0 or an interface address",
+                self.config.host
+            );
+        }
+        Ok(())
+    }
+
+    /// The link's proxy; `proxy` is validated when the client starts
+    fn proxy(&self) -> Option<Proxy> {
+        Proxy::from_config(&self.config).ok().flatten()
+    }
+
+    /// Connect to the configured host/port, through the proxy if one is set
+    async fn connect_tcp(&self) -> anyhow::Result<TcpStream> {
+        let Some(proxy) = self.proxy() else {
+            return Ok(TcpStream::connect(self.config.addr()).await?);
+        };
+        info!(proxy = %proxy.addr, "connecting through proxy");
+        proxy
+            .connect(&self.config.host, self.config.port)
+            .await
+            .with_context(|| {
+                format!(
+                    "proxy {} failed to reach {}",
+                    proxy.addr,
+                    self.config.addr()
+                )
+            })
+    }
+
+    /// The link's frame size limit
+    fn frame_limit(&self) -> FrameLimit {
+        FrameLimit {
+            max_len: self.config.max_frame_bytes,
+            policy: self.config.oversize_policy,
+            stats: self.stats.clone(),
+        }
+    }
+
+    /// The link's charset transcoder; `charset` is validated when the client starts
+    fn transcoder(&self) -> Option<Transcoder> {
+        Transcoder::from_config(&self.config).ok().flatten()
+    }
+
+    /// The link's decompressor if it applies in `mode`
+    fn decompressor(&self, mode: DecompressMode) -> Option<Decompressor> {
+        if self.config.decompress_mode != mode {
+            return None;
+        }
+        Decompressor::new(self.config.decompress, self.config.decompress_max_bytes)
+    }
+
+    /// Decompress a datagram or WebSocket message in `frame` mode. Returns
+    /// `None`, after logging, if it is corrupt or too large.
+    async fn decompress_frame<'a>(&self, data: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        let Some(decompressor) = self.decompressor(DecompressMode::Frame) else {
+            return Some(Cow::Borrowed(data));
+        };
+        match decompressor.frame(data).await {
+            Ok(data) => Some(Cow::Owned(data)),
+            Err(e) => {
+                warn!(error = %e, len = data.len(), "failed to decompress frame, skipping");
+                None
+            }
         }
     }
 
-    /// Connect to a TCP server and read line-delimited ASCII messages
+    /// Decompress a frame split from a stream in `frame` mode, then transcode
+    /// it from `charset`. Returns `None` if it is skipped as corrupt.
+    async fn unpack_frame(
+        &self,
+        data: Vec<u8>,
+        charset: Option<Transcoder>,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let decompressed = match self.decompress_frame(&data).await {
+            Some(Cow::Owned(decompressed)) => Some(decompressed),
+            Some(Cow::Borrowed(_)) => None,
+            None => return Ok(None),
+        };
+        transcode(decompressed.unwrap_or(data), charset).map(Some)
+    }
+
+    /// Connect to a TCP server and read line-delimited ASCII messages, with
+    /// Telnet commands removed when `telnet` is set
     async fn run_tcp<F>(
         &self,
         message_handler: &mut F,
         shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
     ) -> anyhow::Result<()>
     where
-        F: FnMut(Vec<u8>) -> anyhow::Result<()>,
+        F: FrameSink,
     {
         let addr = self.config.addr();
         info!(addr = %addr, "connecting TCP stream");
 
-        let stream = TcpStream::connect(&addr).await?;
+        let stream = self.connect_tcp().await?;
         info!(addr = %addr, "TCP stream connected");
 
-        let reader = BufReader::new(stream);
-        let mut lines = reader.lines();
+        let peer = stream.peer_addr()?.to_string();
+        let local = stream.local_addr()?.to_string();
+        if self.config.telnet {
+            let stream = TelnetStream::new(stream, TelnetFilter::refuse_all());
+            return self
+                .read_frames(
+                    stream,
+                    "Telnet",
+                    Some(&peer),
+                    Some(&local),
+                    message_handler,
+                    shutdown_rx,
+                )
+                .await;
+        }
+        self.read_frames(
+            stream,
+            "TCP",
+            Some(&peer),
+            Some(&local),
+            message_handler,
+            shutdown_rx,
+        )
+        .await
+    }
+
+    /// Connect to a terminal server with Telnet COM port control (RFC 2217),
+    /// request the configured serial settings and read line-delimited
+    /// messages with the Telnet commands removed
+    async fn run_rfc2217<F>(
+        &self,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        F: FrameSink,
+    {
+        let addr = self.config.addr();
+        info!(addr = %addr, "connecting RFC 2217 terminal server");
+
+        let stream = self.connect_tcp().await?;
+        let settings = ComPortSettings::from_config(&self.config);
+        info!(addr = %addr, settings = ?settings, "RFC 2217 connected");
+
+        let peer = stream.peer_addr()?.to_string();
+        let local = stream.local_addr()?.to_string();
+        let stream = TelnetStream::new(stream, TelnetFilter::rfc2217(settings));
+        self.read_frames(
+            stream,
+            "RFC 2217",
+            Some(&peer),
+            Some(&local),
+            message_handler,
+            shutdown_rx,
+        )
+        .await
+    }
+
+    /// Bind the configured host/port and read line-delimited messages from
+    /// every client that connects.
+    ///
+    /// Clients are checked against the source filter and `max_clients` on
+    /// accept. Each accepted client is the next connection generation, with
+    /// its own sequence numbers. A client that trips the rate limits under
+    /// `rate_limit_policy=disconnect` is closed, and its address is refused
+    /// until `rate_limit_cooldown_ms` has passed.
+    async fn run_tcp_listen<F>(
+        &self,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        F: FrameSink,
+    {
+        let addr = self.config.addr();
+        let listener = TcpListener::bind(&addr)
+            .await
+            .with_context(|| format!("failed to bind TCP listener on {addr}"))?;
+        let local = listener.local_addr()?.to_string();
+        info!(addr = %local, "TCP listener bound");
+
+        let (frame_tx, mut frame_rx) = mpsc::channel(LISTEN_QUEUE_LEN);
+        let mut clients = FuturesUnordered::new();
+        let mut stops = HashMap::new();
+        let mut cooldowns: HashMap<IpAddr, Instant> = HashMap::new();
+        let mut rejections = RejectionLog::default();
+        let mut refusals = RejectionLog::default();
+        let mut next_client = 0;
 
         loop {
             tokio::select! {
                 _ = &mut *shutdown_rx => {
-                    info!("TCP stream shutdown signal received");
+                    info!("TCP listener shutdown signal received");
                     break;
                 }
-                result = lines.next_line() => {
-                    match result {
-                        Ok(Some(line)) => {
-                            debug!(line = %line, "received TCP line");
-                            message_handler(line.into_bytes())?;
+                result = listener.accept() => {
+                    let (stream, peer) = match result {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!(error = %e, "TCP accept error");
+                            continue;
                         }
-                        Ok(None) => {
-                            info!("TCP stream EOF");
-                            break;
+                    };
+                    let ip = peer.ip().to_canonical();
+                    if !self.config.accepts_source(ip) {
+                        LinkStats::incr(&self.stats.sources_rejected);
+                        if let Some(suppressed) = rejections.check(Instant::now()) {
+                            warn!(peer = %peer, suppressed, "TCP client rejected by source filter");
                         }
-                        Err(e) => {
-                            error!(error = %e, "TCP read error");
-                            return Err(e.into());
+                        continue;
+                    }
+                    let now = Instant::now();
+                    cooldowns.retain(|_, until| *until > now);
+                    let refused = if cooldowns.contains_key(&ip) {
+                        Some("rate limit cooldown")
+                    } else if self.config.max_clients > 0
+                        && stops.len() >= self.config.max_clients
+                    {
+                        Some("max_clients reached")
+                    } else {
+                        None
+                    };
+                    if let Some(reason) = refused {
+                        LinkStats::incr(&self.stats.clients_refused);
+                        if let Some(suppressed) = refusals.check(now) {
+                            warn!(peer = %peer, reason, suppressed, "TCP client refused");
                         }
+                        continue;
+                    }
+
+                    next_client += 1;
+                    info!(peer = %peer, client = next_client, "TCP client connected");
+                    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+                    stops.insert(next_client, stop_tx);
+                    clients.push(self.serve_client(
+                        next_client,
+                        stream,
+                        peer,
+                        &local,
+                        frame_tx.clone(),
+                        stop_rx,
+                    ));
+                }
+                Some((client, peer, frame)) = frame_rx.recv() => {
+                    match message_handler.handle(frame).await {
+                        // Frames still queued from a client that was already
+                        // closed are dropped without closing it again
+                        Err(e) if e.is::<RateLimitExceeded>() => {
+                            if stops.remove(&client).is_some() {
+                                warn!(
+                                    peer = %peer,
+                                    client,
+                                    cooldown_ms = self.config.rate_limit_cooldown_ms,
+                                    "rate limit exceeded, disconnecting TCP client"
+                                );
+                                LinkStats::incr(&self.stats.rate_limit_disconnects);
+                                let cooldown =
+                                    Duration::from_millis(self.config.rate_limit_cooldown_ms);
+                                let until = Instant::now() + cooldown;
+                                cooldowns.insert(peer.ip().to_canonical(), until);
+                            }
+                        }
+                        result => result?,
+                    }
+                }
+                Some((client, peer, result)) = clients.next(), if !clients.is_empty() => {
+                    stops.remove(&client);
+                    match result {
+                        Ok(()) => info!(peer = %peer, client, "TCP client disconnected"),
+                        Err(e) => warn!(peer = %peer, client, error = %e, "TCP client closed"),
                     }
                 }
             }
@@ -79,22 +622,114 @@ impl StreamClient {
         Ok(())
     }
 
-    /// Bind a UDP socket and receive datagrams from the remote server
+    /// Read frames from one listen-mode client into `frames` until it
+    /// disconnects or `stop` fires or is dropped. Reading pauses while
+    /// `frames` is full.
+    async fn serve_client(
+        &self,
+        client: u64,
+        stream: TcpStream,
+        peer: SocketAddr,
+        local: &str,
+        frames: mpsc::Sender<(u64, SocketAddr, Frame)>,
+        mut stop: tokio::sync::oneshot::Receiver<()>,
+    ) -> (u64, SocketAddr, anyhow::Result<()>) {
+        let peer_addr = peer.to_string();
+        let mut forward = ClientFrames {
+            client,
+            peer,
+            seq: 0,
+            frames,
+        };
+        let result = self
+            .read_frames(
+                stream,
+                "TCP",
+                Some(&peer_addr),
+                Some(local),
+                &mut forward,
+                &mut stop,
+            )
+            .await;
+        (client, peer, result)
+    }
+
+    /// Connect to a Unix domain stream socket and read line-delimited ASCII messages
+    async fn run_unix<F>(
+        &self,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        F: FrameSink,
+    {
+        let path = &self.config.path;
+        info!(path = %path, "connecting Unix stream");
+
+        let stream = connect_unix_stream(path).await?;
+        info!(path = %path, "Unix stream connected");
+
+        self.read_frames(stream, "Unix", None, None, message_handler, shutdown_rx)
+            .await
+    }
+
+    /// Bind a UDP socket and receive datagrams from the remote server.
+    ///
+    /// In broadcast mode the configured host/port is bound locally instead and
+    /// datagrams are accepted from any sender that passes the source filter.
+    ///
+    /// Through a SOCKS5 proxy, datagrams arrive from the proxy's UDP relay
+    /// with their original sender in a header, and the link ends when the
+    /// proxy closes the association. An empty datagram is sent to the target
+    /// through the relay first, since relays only forward replies from hosts
+    /// the client has sent to. The proxy resolves a host name target, and
+    /// relayed datagrams from any sender other than the target are dropped
+    /// like on a connected socket (see [`proxy::RelayTarget`]).
     async fn run_udp<F>(
         &self,
         message_handler: &mut F,
         shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
     ) -> anyhow::Result<()>
     where
-        F: FnMut(Vec<u8>) -> anyhow::Result<()>,
+        F: FrameSink,
     {
         let addr = self.config.addr();
-        info!(addr = %addr, "binding UDP socket");
-
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        socket.connect(&addr).await?;
-        info!(addr = %addr, "UDP socket connected");
+        let mut association = None;
+        let mut target = None;
+        let socket = match (&self.config.udp_mode, self.proxy()) {
+            (UdpMode::Connected, Some(proxy)) => {
+                info!(addr = %addr, proxy = %proxy.addr, "requesting UDP relay from proxy");
+                let (socket, relay) = proxy
+                    .udp_associate()
+                    .await
+                    .with_context(|| format!("proxy {} refused UDP relay", proxy.addr))?;
+                let relay_target = RelayTarget::new(&self.config.host, self.config.port);
+                socket
+                    .send(&proxy::udp_datagram(relay_target.addr(), &[])?)
+                    .await?;
+                info!(addr = %addr, relay = %relay.relay, "UDP relay associated");
+                association = Some(relay);
+                target = Some(relay_target);
+                socket
+            }
+            (UdpMode::Connected, None) => {
+                info!(addr = %addr, "binding UDP socket");
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(&addr).await?;
+                info!(addr = %addr, "UDP socket connected");
+                socket
+            }
+            (UdpMode::Broadcast, _) => {
+                let socket = bind_broadcast_socket(&addr).await?;
+                info!(addr = %addr, "UDP broadcast socket bound");
+                socket
+            }
+        };
 
+        let local = socket.local_addr()?.to_string();
+        let limit = self.frame_limit();
+        let charset = self.transcoder();
+        let mut rejections = RejectionLog::default();
         let mut buf = vec![0u8; 65535];
 
         loop {
@@ -103,16 +738,59 @@ impl StreamClient {
                     info!("UDP stream shutdown signal received");
                     break;
                 }
-                result = socket.recv(&mut buf) => {
+                e = relay_closed(association.as_mut()) => {
+                    error!(error = %e, "UDP relay lost");
+                    return Err(e.into());
+                }
+                result = self.throttled(socket.recv_from(&mut buf)) => {
                     match result {
-                        Ok(n) => {
-                            if let Ok(line) = std::str::from_utf8(&buf[..n]) {
-                                let line = line.trim_end_matches('\n').trim_end_matches('\r');
-                                debug!(line = %line, "received UDP datagram");
-                                message_handler(line.as_bytes().to_vec())?;
-                            } else {
-                                debug!("received non-UTF8 UDP datagram, skipping");
+                        Ok((n, peer)) => {
+                            let (peer, datagram) = match &mut target {
+                                None => (SocksAddr::Ip(peer), &buf[..n]),
+                                Some(target) => match proxy::parse_udp_datagram(&buf[..n]) {
+                                    Some((sender, datagram)) if target.accepts(&sender) => {
+                                        (sender, datagram)
+                                    }
+                                    Some((sender, _)) => {
+                                        debug!(sender = %sender, "dropping relayed datagram from another sender");
+                                        continue;
+                                    }
+                                    None => {
+                                        debug!(len = n, "dropping fragmented or malformed relayed datagram");
+                                        continue;
+                                    }
+                                },
+                            };
+                            // A sender named by host is the configured target
+                            let source = match &peer {
+                                SocksAddr::Ip(addr) => Some(addr.ip()),
+                                SocksAddr::Domain(..) => None,
+                            };
+                            if source.is_some_and(|ip| !self.config.accepts_source(ip)) {
+                                LinkStats::incr(&self.stats.sources_rejected);
+                                if let Some(suppressed) = rejections.check(Instant::now()) {
+                                    warn!(
+                                        peer = %peer,
+                                        suppressed,
+                                        "UDP datagram rejected by source filter"
+                                    );
+                                }
+                                continue;
                             }
+                            let Some(data) = self.decompress_frame(datagram).await else {
+                                continue;
+                            };
+                            let Some(len) = limit.check(data.len())? else {
+                                continue;
+                            };
+                            handle_datagram(
+                                &data[..text_len(&data, len)],
+                                charset,
+                                Some(&peer.to_string()),
+                                Some(&local),
+                                message_handler,
+                            )
+                            .await?;
                         }
                         Err(e) => {
                             error!(error = %e, "UDP recv error");
@@ -125,4 +803,2184 @@ impl StreamClient {
 
         Ok(())
     }
+
+    /// Bind a Unix domain datagram socket at the configured path and receive
+    /// datagrams written to it.
+    ///
+    /// Unix datagram sockets are connectionless and an unbound client cannot
+    /// receive, so the provider owns the path the same way a syslog-style
+    /// daemon does. A stale socket file left at the path is replaced.
+    async fn run_unixgram<F>(
+        &self,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        F: FrameSink,
+    {
+        let path = &self.config.path;
+        let socket = bind_unix_datagram(path)?;
+        info!(path = %path, "Unix datagram socket bound");
+
+        let limit = self.frame_limit();
+        let charset = self.transcoder();
+        let mut buf = vec![0u8; 65535];
+
+        loop {
+            tokio::select! {
+                _ = &mut *shutdown_rx => {
+                    info!("Unix datagram shutdown signal received");
+                    break;
+                }
+                result = self.throttled(socket.recv_from(&mut buf)) => {
+                    match result {
+                        Ok((n, peer)) => {
+                            let peer = peer.as_pathname().map(|p| p.display().to_string());
+                            let Some(data) = self.decompress_frame(&buf[..n]).await else {
+                                continue;
+                            };
+                            let Some(len) = limit.check(data.len())? else {
+                                continue;
+                            };
+                            let data = &data[..text_len(&data, len)];
+                            handle_datagram(data, charset, peer.as_deref(), Some(path.as_str()), message_handler).await?;
+                        }
+                        Err(e) => {
+                            error!(error = %e, "Unix datagram recv error");
+                            return Err(e.into());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Connect to a WebSocket server and forward each text or binary message.
+    ///
+    /// Server pings are answered automatically. When a ping interval is set, the
+    /// client pings the server and treats a missing pong by the next tick as a
+    /// dead connection.
+    async fn run_websocket<F>(
+        &self,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        F: FrameSink,
+    {
+        let url = self.config.url();
+        info!(url = %url, "connecting WebSocket stream");
+
+        let request = build_websocket_request(&self.config)?;
+        let (ws, response) = tokio_tungstenite::connect_async(request).await?;
+        info!(
+            url = %url,
+            subprotocol = ?response.headers().get(SEC_WEBSOCKET_PROTOCOL),
+            "WebSocket stream connected"
+        );
+
+        let (mut sink, mut stream) = ws.split();
+        let mut ping_timer = (self.config.ws_ping_interval_secs > 0).then(|| {
+            let period = Duration::from_secs(self.config.ws_ping_interval_secs);
+            tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+        });
+        let mut awaiting_pong = false;
+        let limit = self.frame_limit();
+
+        loop {
+            tokio::select! {
+                _ = &mut *shutdown_rx => {
+                    info!("WebSocket stream shutdown signal received");
+                    let _ = sink.send(Message::Close(None)).await;
+                    break;
+                }
+                _ = async {
+                    match ping_timer.as_mut() {
+                        Some(timer) => timer.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if awaiting_pong {
+                        anyhow::bail!("WebSocket ping timeout");
+                    }
+                    sink.send(Message::Ping(Vec::new())).await?;
+                    awaiting_pong = true;
+                }
+                result = self.throttled(stream.next()) => {
+                    match result {
+                        Some(Ok(Message::Text(text))) => {
+                            debug!(text = %text, "received WebSocket text message");
+                            let mut data = text.into_bytes();
+                            let Some(len) = limit.check(data.len())? else {
+                                continue;
+                            };
+                            data.truncate(text_len(&data, len));
+                            message_handler.handle(data.into()).await?;
+                        }
+                        Some(Ok(Message::Binary(data))) => {
+                            debug!(len = data.len(), "received WebSocket binary message");
+                            let Some(data) = self.decompress_frame(&data).await else {
+                                continue;
+                            };
+                            let Some(len) = limit.check(data.len())? else {
+                                continue;
+                            };
+                            let mut data = data.into_owned();
+                            data.truncate(len);
+                            message_handler.handle(data.into()).await?;
+                        }
+                        Some(Ok(Message::Pong(_))) => awaiting_pong = false,
+                        Some(Ok(Message::Ping(_))) => debug!("received WebSocket ping"),
+                        Some(Ok(Message::Close(frame))) => {
+                            info!(frame = ?frame, "WebSocket stream closed by server");
+                            break;
+                        }
+                        Some(Ok(Message::Frame(_))) => {}
+                        Some(Err(e)) => {
+                            error!(error = %e, "WebSocket read error");
+                            return Err(e.into());
+                        }
+                        None => {
+                            info!("WebSocket stream EOF");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Open a long-lived HTTP response and forward each non-empty NDJSON line
+    async fn run_http_stream<F>(
+        &self,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        F: FrameSink,
+    {
+        let url = self.config.url();
+        info!(url = %url, "connecting HTTP stream");
+
+        let mut response = self
+            .http_request(&reqwest::Client::new(), None)
+            .send()
+            .await?
+            .error_for_status()?;
+        info!(url = %url, status = %response.status(), "HTTP stream connected");
+
+        let charset = self.transcoder();
+        let mut lines = LineSplitter::new(self.frame_limit());
+        loop {
+            tokio::select! {
+                _ = &mut *shutdown_rx => {
+                    info!("HTTP stream shutdown signal received");
+                    break;
+                }
+                result = self.throttled(response.chunk()) => {
+                    match result {
+                        Ok(Some(chunk)) => {
+                            for line in lines.push(&chunk)? {
+                                if line.is_empty() {
+                                    continue;
+                                }
+                                if let Some(data) = self.unpack_frame(line, charset).await? {
+                                    message_handler.handle(data.into()).await?;
+                                }
+                            }
+                        }
+                        Ok(None) => {
+                            if let Some(line) = lines.finish() {
+                                if let Some(data) = self.unpack_frame(line, charset).await? {
+                                    message_handler.handle(data.into()).await?;
+                                }
+                            }
+                            info!("HTTP stream EOF");
+                            break;
+                        }
+                        Err(e) => {
+                            error!(error = %e, "HTTP stream read error");
+                            return Err(e.into());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Consume a Server-Sent Events stream, forwarding each event with its
+    /// `event:` type as the subject.
+    ///
+    /// As required by the SSE model, a dropped connection is re-established
+    /// after the server-provided `retry:` delay, sending `Last-Event-ID` so the
+    /// server can resume. An HTTP error status or `204 No Content` ends the stream.
+    async fn run_sse<F>(
+        &self,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        F: FrameSink,
+    {
+        let url = self.config.url();
+        let client = reqwest::Client::new();
+        let mut parser = SseParser::default();
+        let mut generation = 0;
+
+        loop {
+            info!(url = %url, last_event_id = ?parser.last_event_id, "connecting SSE stream");
+            let request = self
+                .http_request(&client, parser.last_event_id.as_deref())
+                .header(reqwest::header::ACCEPT, "text/event-stream")
+                .header(reqwest::header::CACHE_CONTROL, "no-cache");
+
+            match request.send().await {
+                Ok(response) if response.status() == reqwest::StatusCode::NO_CONTENT => {
+                    info!("SSE stream ended by server (204 No Content)");
+                    return Ok(());
+                }
+                Ok(response) => {
+                    let mut response = response.error_for_status()?;
+                    generation += 1;
+                    info!(url = %url, generation, "SSE stream connected");
+
+                    let mut lines = LineSplitter::new(self.frame_limit());
+                    loop {
+                        tokio::select! {
+                            _ = &mut *shutdown_rx => {
+                                info!("SSE stream shutdown signal received");
+                                return Ok(());
+                            }
+                            result = self.throttled(response.chunk()) => {
+                                match result {
+                                    Ok(Some(chunk)) => {
+                                        for line in lines.push(&chunk)? {
+                                            let line = String::from_utf8_lossy(&line);
+                                            if let Some(event) = parser.push_line(&line) {
+                                                debug!(event = ?event.event, "received SSE event");
+                                                let Some(data) = self
+                                                    .unpack_frame(event.data.into_bytes(), None)
+                                                    .await?
+                                                else {
+                                                    continue;
+                                                };
+                                                let mut frame = Frame {
+                                                    data,
+                                                    subject: event.event,
+                                                    ..Default::default()
+                                                };
+                                                frame.meta.generation = generation;
+                                                message_handler.handle(frame).await?;
+                                            }
+                                        }
+                                    }
+                                    Ok(None) => {
+                                        info!("SSE stream EOF");
+                                        break;
+                                    }
+                                    Err(e) => {
+                                        warn!(error = %e, "SSE stream read error");
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    parser.reset_event();
+                }
+                Err(e) => warn!(error = %e, "SSE connect failed"),
+            }
+
+            let delay = Duration::from_millis(parser.retry_ms.unwrap_or(DEFAULT_SSE_RETRY_MS));
+            tokio::select! {
+                _ = &mut *shutdown_rx => {
+                    info!("SSE stream shutdown signal received");
+                    return Ok(());
+                }
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+    }
+
+    /// Follow a local file like `tail -F` and read line-delimited messages
+    /// appended to it, through the same framing as TCP.
+    ///
+    /// A file that does not exist yet is waited for and read from its start.
+    /// With `file_checkpoint` set, the position after the last delivered frame
+    /// is saved in the background at most once a second, and on shutdown.
+    async fn run_file<F>(
+        &self,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        F: FrameSink,
+    {
+        let path = Path::new(&self.config.path);
+        let poll_interval = Duration::from_millis(self.config.file_poll_interval_ms);
+        let (checkpoint, saved) = if self.config.file_checkpoint.is_empty() {
+            (None, None)
+        } else {
+            let (checkpoint, saved) = Checkpoint::load(Path::new(&self.config.file_checkpoint))
+                .await
+                .with_context(|| {
+                    format!("failed to read checkpoint {}", self.config.file_checkpoint)
+                })?;
+            (Some(checkpoint), saved)
+        };
+
+        let mut start = self.config.file_start;
+        let tail = loop {
+            match FileTail::open(path, start, saved, poll_interval) {
+                Ok(tail) => break tail,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    if start != FileStart::Beginning {
+                        info!(path = %path.display(), "waiting for file to be created");
+                        start = FileStart::Beginning;
+                    }
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to open {}", path.display()))
+                }
+            }
+            tokio::select! {
+                _ = &mut *shutdown_rx => {
+                    info!("file stream shutdown signal received");
+                    return Ok(());
+                }
+                _ = tokio::time::sleep(poll_interval) => {}
+            }
+        };
+        info!(path = %path.display(), position = ?tail.position(), "following file");
+
+        // Position of the next unread frame: what the tail has read, less what
+        // is still buffered
+        let position = |reader: &BufReader<FileTail>| {
+            let mut position = reader.get_ref().position();
+            position.offset = position.offset.saturating_sub(reader.buffer().len() as u64);
+            position
+        };
+        // A read cut short by shutdown may have consumed part of a line, so the
+        // final checkpoint is the end of the last delivered frame
+        let mut delivered = tail.position();
+        let mut reader = BufReader::new(tail);
+        let result = self
+            .read_buffered_frames(
+                &mut reader,
+                &self.frame_limit(),
+                "file",
+                None,
+                None,
+                message_handler,
+                shutdown_rx,
+                |reader| {
+                    delivered = position(reader);
+                    if let Some(checkpoint) = &checkpoint {
+                        checkpoint.update(delivered);
+                    }
+                },
+            )
+            .await;
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.finish(delivered).await;
+        }
+        result
+    }
+
+    /// Read frames back from a capture file until its end or shutdown, paced
+    /// by their receive timestamps divided by `replay_speed`. Frames are
+    /// delivered as captured, without decompression or transcoding.
+    async fn run_replay<F>(
+        &self,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        F: FrameSink,
+    {
+        let path = &self.config.path;
+        let speed = self.config.replay_speed;
+        let mut reader = CaptureReader::open(Path::new(path))
+            .await
+            .with_context(|| format!("failed to open capture file {path}"))?;
+        info!(path = %path, format = ?reader.format(), speed, "replaying capture");
+
+        // Capture timestamp of the first frame and when it was replayed
+        let mut start: Option<(u64, Instant)> = None;
+        let mut frames = 0u64;
+        loop {
+            let captured = tokio::select! {
+                _ = &mut *shutdown_rx => {
+                    info!("replay shutdown signal received");
+                    return Ok(());
+                }
+                result = self.throttled(reader.next()) => result,
+            };
+            let captured = match captured {
+                Ok(Some(captured)) => captured,
+                Ok(None) => {
+                    info!(path = %path, frames, "replay finished");
+                    return Ok(());
+                }
+                Err(e) => {
+                    error!(error = %e, "replay read error");
+                    return Err(e.into());
+                }
+            };
+
+            if speed > 0.0 {
+                let (first_us, started) =
+                    *start.get_or_insert((captured.timestamp_us, Instant::now()));
+                let offset = Duration::from_micros(captured.timestamp_us.saturating_sub(first_us));
+                tokio::select! {
+                    _ = &mut *shutdown_rx => {
+                        info!("replay shutdown signal received");
+                        return Ok(());
+                    }
+                    _ = tokio::time::sleep_until((started + offset.div_f64(speed)).into()) => {}
+                }
+            }
+            frames += 1;
+            message_handler.handle(Frame::from(captured.data)).await?;
+        }
+    }
+
+    /// Read framed messages from a connected stream until EOF or shutdown.
+    /// In `stream` decompression mode the bytes are decompressed first and
+    /// frames are also limited to `decompress_max_bytes`.
+    async fn read_frames<R, F>(
+        &self,
+        stream: R,
+        transport: &str,
+        peer: Option<&str>,
+        local: Option<&str>,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> anyhow::Result<()>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        F: FrameSink,
+    {
+        let mut limit = self.frame_limit();
+        let stream: Box<dyn AsyncRead + Unpin + Send> =
+            match self.decompressor(DecompressMode::Stream) {
+                Some(decompressor) => {
+                    limit.max_len = limit.max_len.min(decompressor.max_bytes);
+                    decompressor.stream(BufReader::new(stream)).await?
+                }
+                None => Box::new(stream),
+            };
+        self.read_buffered_frames(
+            &mut BufReader::new(stream),
+            &limit,
+            transport,
+            peer,
+            local,
+            message_handler,
+            shutdown_rx,
+            |_| {},
+        )
+        .await
+    }
+
+    /// Read framed messages from `reader` until EOF or shutdown, calling
+    /// `after_frame` once each frame has been handled
+    #[allow(clippy::too_many_arguments)]
+    async fn read_buffered_frames<R, F, A>(
+        &self,
+        reader: &mut R,
+        limit: &FrameLimit,
+        transport: &str,
+        peer: Option<&str>,
+        local: Option<&str>,
+        message_handler: &mut F,
+        shutdown_rx: &mut tokio::sync::oneshot::Receiver<()>,
+        mut after_frame: A,
+    ) -> anyhow::Result<()>
+    where
+        R: AsyncBufRead + Unpin,
+        F: FrameSink,
+        A: FnMut(&R),
+    {
+        let framing = self.framing();
+        // In `frame` decompression mode frames are transcoded once decompressed
+        let (framing_charset, charset) = match self.decompressor(DecompressMode::Frame) {
+            Some(_) => (None, self.transcoder()),
+            None => (self.transcoder(), None),
+        };
+
+        loop {
+            tokio::select! {
+                _ = &mut *shutdown_rx => {
+                    info!("{} stream shutdown signal received", transport);
+                    break;
+                }
+                result = self.throttled(next_frame(&mut *reader, framing, limit, framing_charset)) => {
+                    match result {
+                        Ok(Some(data)) => {
+                            let Some(data) = self.unpack_frame(data, charset).await? else {
+                                continue;
+                            };
+                            debug!(len = data.len(), "received {} frame", transport);
+                            message_handler
+                                .handle(Frame::from(data).with_addrs(peer, local))
+                                .await?;
+                            after_frame(reader);
+                        }
+                        Ok(None) => {
+                            info!("{} stream EOF", transport);
+                            break;
+                        }
+                        Err(e) => {
+                            error!(error = %e, "{} read error", transport);
+                            return Err(e.into());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build a GET request for the configured URL with the configured headers
+    fn http_request(
+        &self,
+        client: &reqwest::Client,
+        last_event_id: Option<&str>,
+    ) -> reqwest::RequestBuilder {
+        let mut request = client.get(self.config.url());
+        for (name, value) in &self.config.http_headers {
+            request = request.header(name, value);
+        }
+        if let Some(id) = last_event_id {
+            request = request.header("Last-Event-ID", id);
+        }
+        request
+    }
+}
+
+/// Receiver of the frames read from a stream
+pub trait FrameSink {
+    /// Handle a frame, waiting while the receiver cannot take more
+    fn handle(&mut self, frame: Frame) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+impl<F> FrameSink for F
+where
+    F: FnMut(Frame) -> anyhow::Result<()>,
+{
+    fn handle(&mut self, frame: Frame) -> impl Future<Output = anyhow::Result<()>> + Send {
+        std::future::ready(self(frame))
+    }
+}
+
+/// Wraps a link's [`FrameSink`]: captures each frame, charges it against the
+/// rate limits and stamps it with the link's receive metadata
+struct LinkFrames<'a, F> {
+    client: &'a StreamClient,
+    sink: F,
+    capture: Option<CaptureThread>,
+    default_peer: String,
+    connection_id: u64,
+    origin: Instant,
+    generation: u64,
+    seq: u64,
+    /// Generation of the last connection before a rate limit disconnect
+    base_generation: u64,
+}
+
+impl<F> LinkFrames<'_, F> {
+    /// Capture and stamp `frame`. Returns `false` if the rate limits drop it,
+    /// or a [`RateLimitExceeded`] error to disconnect.
+    fn stamp(&mut self, frame: &mut Frame) -> anyhow::Result<bool> {
+        if let Some(writer) = &mut self.capture {
+            let peer = frame.meta.peer.as_deref().unwrap_or(&self.default_peer);
+            if !writer.write(&frame.data, SystemTime::now(), Some(peer)) {
+                self.capture = None;
+            }
+        }
+        if !self.client.admit(frame.data.len())? {
+            return Ok(false);
+        }
+        let frame_generation = self.base_generation + frame.meta.generation.max(1);
+        if frame_generation != self.generation {
+            self.generation = frame_generation;
+            self.seq = 0;
+        }
+        self.seq += 1;
+        // Listen mode numbers the frames of each client itself
+        if frame.meta.seq == 0 {
+            frame.meta.seq = self.seq;
+        }
+        frame.meta.generation = self.generation;
+        frame.meta.connection_id = self.connection_id;
+        frame.meta.protocol = self.client.config.protocol.as_str();
+        frame.meta.received_at = Some(SystemTime::now());
+        frame.meta.received_mono = Some(Instant::now().max(self.origin));
+        if frame.meta.peer.is_none() {
+            frame.meta.peer = Some(self.default_peer.clone());
+        }
+        Ok(true)
+    }
+}
+
+impl<F> FrameSink for LinkFrames<'_, F>
+where
+    F: FrameSink + Send,
+{
+    fn handle(&mut self, mut frame: Frame) -> impl Future<Output = anyhow::Result<()>> + Send {
+        let admitted = self.stamp(&mut frame);
+        let sink = &mut self.sink;
+        async move {
+            if admitted? {
+                sink.handle(frame).await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Numbers the frames of one listen-mode client and queues them for the
+/// listener
+struct ClientFrames {
+    client: u64,
+    peer: SocketAddr,
+    seq: u64,
+    frames: mpsc::Sender<(u64, SocketAddr, Frame)>,
+}
+
+impl FrameSink for ClientFrames {
+    fn handle(&mut self, mut frame: Frame) -> impl Future<Output = anyhow::Result<()>> + Send {
+        self.seq += 1;
+        frame.meta.seq = self.seq;
+        frame.meta.generation = self.client;
+        let item = (self.client, self.peer, frame);
+        async move {
+            self.frames
+                .send(item)
+                .await
+                .map_err(|_| anyhow::anyhow!("TCP listener stopped"))
+        }
+    }
+}
+
+/// Limits a repeated warning, such as a rejected peer, to one per
+/// [`REJECTION_WARN_INTERVAL`]
+#[derive(Debug, Default)]
+struct RejectionLog {
+    last: Option<Instant>,
+    suppressed: u64,
+}
+
+impl RejectionLog {
+    /// Returns the number of warnings suppressed since the last one if a
+    /// warning may be logged at `now`
+    fn check(&mut self, now: Instant) -> Option<u64> {
+        match self.last {
+            Some(last) if now.saturating_duration_since(last) < REJECTION_WARN_INTERVAL => {
+                self.suppressed += 1;
+                None
+            }
+            _ => {
+                self.last = Some(now);
+                Some(std::mem::take(&mut self.suppressed))
+            }
+        }
+    }
+}
+
+/// Maximum frame size, the policy for larger frames and the counters it
+/// updates
+#[derive(Debug, Clone)]
+struct FrameLimit {
+    max_len: usize,
+    policy: OversizePolicy,
+    stats: Arc<LinkStats>,
+}
+
+impl FrameLimit {
+    /// Apply the oversize policy to a frame of `len` bytes, returning how many
+    /// bytes to keep, `None` to discard it, or an error to disconnect
+    fn check(&self, len: usize) -> std::io::Result<Option<usize>> {
+        if len <= self.max_len {
+            return Ok(Some(len));
+        }
+        match self.policy {
+            OversizePolicy::Truncate => {
+                LinkStats::incr(&self.stats.frames_truncated);
+                debug!(len, max = self.max_len, "truncating oversized frame");
+                Ok(Some(self.max_len))
+            }
+            OversizePolicy::Discard => {
+                LinkStats::incr(&self.stats.frames_discarded);
+                debug!(len, max = self.max_len, "discarding oversized frame");
+                Ok(None)
+            }
+            OversizePolicy::Disconnect => {
+                LinkStats::incr(&self.stats.oversize_disconnects);
+                warn!(len, max = self.max_len, "disconnecting on oversized frame");
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("frame exceeds {} bytes", self.max_len),
+                ))
+            }
+        }
+    }
+}
+
+/// Shorten `len` so that `data[..len]` does not end inside a UTF-8 sequence
+fn text_len(data: &[u8], len: usize) -> usize {
+    if len >= data.len() {
+        return len;
+    }
+    match std::str::from_utf8(&data[..len]) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => len,
+    }
+}
+
+/// Splits a chunked HTTP body into lines, stripping the `\n` / `\r\n`
+/// terminator and enforcing the frame limit. Incomplete trailing data is
+/// kept until the next chunk.
+struct LineSplitter {
+    pending: Vec<u8>,
+    limit: FrameLimit,
+    /// Dropping the rest of an oversized line
+    skipping: bool,
+}
+
+impl LineSplitter {
+    fn new(limit: FrameLimit) -> Self {
+        LineSplitter {
+            pending: Vec::new(),
+            limit,
+            skipping: false,
+        }
+    }
+
+    /// Append `chunk` and drain every complete line
+    fn push(&mut self, mut chunk: &[u8]) -> std::io::Result<Vec<Vec<u8>>> {
+        let mut lines = Vec::new();
+        while !chunk.is_empty() {
+            let Some(pos) = chunk.iter().position(|&b| b == b'\n') else {
+                if !self.skipping {
+                    self.pending.extend_from_slice(chunk);
+                    // One extra byte may be the `\r` of a `\r\n` terminator
+                    if self.pending.len() > self.limit.max_len.saturating_add(1) {
+                        self.skipping = true;
+                        let mut line = std::mem::take(&mut self.pending);
+                        if let Some(len) = self.limit.check(line.len())? {
+                            line.truncate(text_len(&line, len));
+                            lines.push(line);
+                        }
+                    }
+                }
+                break;
+            };
+            let (part, rest) = (&chunk[..pos], &chunk[pos + 1..]);
+            chunk = rest;
+            if std::mem::take(&mut self.skipping) {
+                continue;
+            }
+            self.pending.extend_from_slice(part);
+            let mut line = std::mem::take(&mut self.pending);
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if let Some(len) = self.limit.check(line.len())? {
+                line.truncate(text_len(&line, len));
+                lines.push(line);
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Take the unterminated last line at the end of the body
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        let line = std::mem::take(&mut self.pending);
+        (!line.is_empty()).then_some(line)
+    }
+}
+
+/// Build the WebSocket handshake request with configured headers and subprotocols
+fn build_websocket_request(
+    config: &ConnectionConfig,
+) -> anyhow::Result<tokio_tungstenite::tungstenite::handshake::client::Request> {
+    let mut request = config.url().into_client_request()?;
+    let headers = request.headers_mut();
+    for (name, value) in &config.ws_headers {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid WebSocket header name {name}"))?,
+            HeaderValue::from_str(value)
+                .with_context(|| format!("invalid value for WebSocket header {name}"))?,
+        );
+    }
+    if !config.ws_subprotocols.is_empty() {
+        headers.insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_str(&config.ws_subprotocols.join(", "))?,
+        );
+    }
+    Ok(request)
+}
+
+/// How messages are delimited on stream transports
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Framing {
+    /// UTF-8 lines terminated by `\n` or `\r\n`
+    Lines,
+    /// RFC 6587 syslog framing: `MSG-LEN SP MSG` when a frame starts with a
+    /// digit, otherwise a newline-terminated message
+    OctetCounting,
+}
+
+/// Read the next message, transcoded from `charset` if set, or `None` at
+/// EOF. Frames above the limit follow its policy; discarded ones are skipped
+/// up to the next delimiter.
+async fn next_frame<R>(
+    reader: &mut R,
+    framing: Framing,
+    limit: &FrameLimit,
+    charset: Option<Transcoder>,
+) -> std::io::Result<Option<Vec<u8>>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    loop {
+        if framing == Framing::OctetCounting
+            && reader
+                .fill_buf()
+                .await?
+                .first()
+                .is_some_and(u8::is_ascii_digit)
+        {
+            let mut len = Vec::new();
+            (&mut *reader)
+                .take(MAX_OCTET_COUNT_DIGITS)
+                .read_until(b' ', &mut len)
+                .await?;
+            let len = std::str::from_utf8(&len)
+                .ok()
+                .and_then(|len| len.strip_suffix(' '))
+                .and_then(|len| len.parse::<usize>().ok())
+                .ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid octet count")
+                })?;
+            let keep = limit.check(len)?;
+            let mut data = vec![0; keep.unwrap_or(0)];
+            reader.read_exact(&mut data).await?;
+            skip_bytes(reader, len - data.len()).await?;
+            if keep.is_none() {
+                continue;
+            }
+            return transcode(data, charset).map(Some);
+        }
+
+        let mut line = Vec::new();
+        // Leave room for the `\r\n` terminator when bounding the read
+        if (&mut *reader)
+            .take(limit.max_len.saturating_add(2) as u64)
+            .read_until(b'\n', &mut line)
+            .await?
+            == 0
+        {
+            return Ok(None);
+        }
+        let terminated = line.ends_with(b"\n");
+        if terminated {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        if line.len() > limit.max_len {
+            let keep = limit.check(line.len())?;
+            if !terminated {
+                skip_line(reader).await?;
+            }
+            let Some(keep) = keep else {
+                continue;
+            };
+            line.truncate(text_len(&line, keep));
+        }
+        let line = transcode(line, charset)?;
+        if framing == Framing::Lines && std::str::from_utf8(&line).is_err() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            ));
+        }
+        return Ok(Some(line));
+    }
+}
+
+/// Consume input up to and including the next `\n`
+async fn skip_line<R>(reader: &mut R) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+{
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(pos) => {
+                reader.consume(pos + 1);
+                return Ok(());
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Consume `len` bytes of input without buffering them
+async fn skip_bytes<R>(reader: &mut R, len: usize) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+{
+    tokio::io::copy(&mut (&mut *reader).take(len as u64), &mut tokio::io::sink()).await?;
+    Ok(())
+}
+
+/// Transcode a frame from `charset` to UTF-8, failing if it contains a
+/// sequence that is invalid under the `error` policy
+fn transcode(data: Vec<u8>, charset: Option<Transcoder>) -> std::io::Result<Vec<u8>> {
+    let Some(charset) = charset else {
+        return Ok(data);
+    };
+    charset.transcode(&data).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("stream did not contain valid {}", charset.name()),
+        )
+    })
+}
+
+/// Convert a received datagram into a message, transcoding it from `charset`
+/// and skipping payloads that are not valid text
+async fn handle_datagram<F>(
+    data: &[u8],
+    charset: Option<Transcoder>,
+    peer: Option<&str>,
+    local: Option<&str>,
+    message_handler: &mut F,
+) -> anyhow::Result<()>
+where
+    F: FrameSink,
+{
+    let data = match charset {
+        Some(charset) => match charset.transcode(data) {
+            Some(data) => Cow::Owned(data),
+            None => {
+                debug!(
+                    charset = charset.name(),
+                    "received invalid datagram, skipping"
+                );
+                return Ok(());
+            }
+        },
+        None => Cow::Borrowed(data),
+    };
+    if let Ok(line) = std::str::from_utf8(&data) {
+        let line = line.trim_end_matches('\n').trim_end_matches('\r');
+        debug!(line = %line, "received datagram");
+        message_handler
+            .handle(Frame::from(line.as_bytes().to_vec()).with_addrs(peer, local))
+            .await?;
+    } else {
+        debug!("received non-UTF8 datagram, skipping");
+    }
+    Ok(())
+}
+
+/// Wait for a SOCKS5 UDP association to end; never resolves without one
+async fn relay_closed(association: Option<&mut UdpAssociation>) -> std::io::Error {
+    match association {
+        Some(association) => association.closed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Bind a UDP socket for broadcast reception with SO_REUSEADDR and SO_BROADCAST
+/// set, so several listeners on the host can share the port.
+async fn bind_broadcast_socket(addr: &str) -> anyhow::Result<UdpSocket> {
+    let addr: SocketAddr = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .with_context(|| format!("failed to resolve bind address {addr}"))?;
+
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Connect to a Unix stream socket, resolving `@name` to the Linux abstract namespace
+async fn connect_unix_stream(path: &str) -> anyhow::Result<UnixStream> {
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        let stream = std::os::unix::net::UnixStream::connect_addr(&addr)?;
+        stream.set_nonblocking(true)?;
+        return Ok(UnixStream::from_std(stream)?);
+    }
+    Ok(UnixStream::connect(path).await?)
+}
+
+/// Bind a Unix datagram socket, resolving `@name` to the Linux abstract namespace
+fn bind_unix_datagram(path: &str) -> anyhow::Result<UnixDatagram> {
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        let socket = std::os::unix::net::UnixDatagram::bind_addr(&addr)?;
+        socket.set_nonblocking(true)?;
+        return Ok(UnixDatagram::from_std(socket)?);
+    }
+
+    use std::os::unix::fs::FileTypeExt;
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {path}"))?;
+    }
+    Ok(UnixDatagram::bind(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        CaptureFormat, CharsetInvalid, RateLimitPolicy, SerialFlowControl, SerialParity,
+    };
+
+    async fn free_udp_port() -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_udp_broadcast_receives_from_any_sender() {
+        let port = free_udp_port().await;
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Udp,
+            udp_mode: UdpMode::Broadcast,
+            host: "0.0.0.0".to_string(),
+            port,
+            allow_cidrs: vec!["127.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        };
+        let client = StreamClient::new(config);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            client
+                .run(
+                    move |frame: Frame| {
+                        tx.send(frame)?;
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+        });
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let received = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                sender
+                    .send_to(b"$GPGGA,hello\r\n", ("127.0.0.1", port))
+                    .await
+                    .unwrap();
+                tokio::select! {
+                    Some(frame) = rx.recv() => break frame,
+                    _ = tokio::time::sleep(std::time::Duration::from_millis(50)) => {}
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for broadcast datagram");
+        assert_eq!(received.data, b"$GPGGA,hello");
+        let sender_addr = sender.local_addr().unwrap().to_string();
+        assert_eq!(received.meta.peer.as_deref(), Some(sender_addr.as_str()));
+        assert_eq!(received.meta.seq, 1);
+        assert_eq!(received.meta.generation, 1);
+        assert_eq!(received.meta.protocol, "udp");
+        assert_eq!(received.meta.local, Some(format!("0.0.0.0:{port}")));
+        assert!(received.meta.connection_id > 0);
+        assert!(received.meta.received_at.is_some());
+
+        shutdown_tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_udp_broadcast_rejects_loopback_host() {
+        for host in ["127.0.0.1", "localhost", "::1"] {
+            let config = ConnectionConfig {
+                protocol: StreamProtocol::Udp,
+                udp_mode: UdpMode::Broadcast,
+                host: host.to_string(),
+                ..Default::default()
+            };
+            let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+            let result = StreamClient::new(config)
+                .run(|_: Frame| Ok(()), shutdown_rx)
+                .await;
+            assert!(result.is_err(), "{host}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_listen_filters_and_caps_clients() {
+        use tokio::io::AsyncWriteExt;
+
+        let port = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Tcp,
+            tcp_mode: TcpMode::Listen,
+            host: "127.0.0.1".to_string(),
+            port,
+            max_clients: 1,
+            deny_cidrs: vec!["127.0.0.2/32".parse().unwrap()],
+            ..Default::default()
+        };
+        let client = StreamClient::new(config);
+        let stats = client.stats();
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            client
+                .run(
+                    move |frame: Frame| {
+                        tx.send(frame)?;
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+        });
+
+        let mut first = loop {
+            match TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        };
+        first.write_all(b"a1\n").await.unwrap();
+        let frame = rx.recv().await.unwrap();
+        assert_eq!((frame.data, frame.meta.generation), (b"a1".to_vec(), 1));
+
+        // A second client exceeds max_clients and a denied address is refused;
+        // both are closed without any data
+        let mut refused = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        assert_eq!(refused.read(&mut [0; 8]).await.unwrap_or(0), 0);
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.2:0".parse().unwrap()).unwrap();
+        let mut denied = socket
+            .connect(format!("127.0.0.1:{port}").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(denied.read(&mut [0; 8]).await.unwrap_or(0), 0);
+
+        first.write_all(b"a2\n").await.unwrap();
+        drop(first);
+        let frame = rx.recv().await.unwrap();
+        assert_eq!((frame.data, frame.meta.seq), (b"a2".to_vec(), 2));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let mut second = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        second.write_all(b"b1\n").await.unwrap();
+        let frame = rx.recv().await.unwrap();
+        assert_eq!(frame.data, b"b1");
+        assert_eq!((frame.meta.generation, frame.meta.seq), (2, 1));
+        let peer = second.local_addr().unwrap().to_string();
+        assert_eq!(frame.meta.peer, Some(peer));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.clients_refused, 1);
+        assert_eq!(snapshot.sources_rejected, 1);
+        shutdown_tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handle_datagram_skips_non_utf8() {
+        let mut received = Vec::new();
+        let mut handler = |frame: Frame| {
+            received.push(frame.data);
+            Ok(())
+        };
+        handle_datagram(&[0xff, 0xfe], None, None, None, &mut handler)
+            .await
+            .unwrap();
+        handle_datagram(b"ok\n", None, None, None, &mut handler)
+            .await
+            .unwrap();
+
+        let charset = |charset: &str| {
+            Transcoder::from_config(&ConnectionConfig {
+                charset: charset.to_string(),
+                charset_invalid: CharsetInvalid::Error,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let latin1 = charset("iso-8859-1");
+        handle_datagram(b"21\xb0C\r\n", latin1, None, None, &mut handler)
+            .await
+            .unwrap();
+        let sjis = charset("shift_jis");
+        handle_datagram(b"\x81\x20", sjis, None, None, &mut handler)
+            .await
+            .unwrap();
+        assert_eq!(received, vec![b"ok".to_vec(), "21°C".as_bytes().to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_unix_stream_reads_lines() {
+        let dir = std::env::temp_dir().join(format!("tcp-udp-unix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stream.sock");
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Unix,
+            path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut received = Vec::new();
+            StreamClient::new(config)
+                .run(
+                    |frame: Frame| {
+                        received.push(frame.data);
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+                .map(|_| received)
+        });
+
+        let (mut server, _) = listener.accept().await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut server, b"one\ntwo\n")
+            .await
+            .unwrap();
+        drop(server);
+
+        let received = task.await.unwrap().unwrap();
+        assert_eq!(received, vec![b"one".to_vec(), b"two".to_vec()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_capture_then_replay() {
+        let dir = std::env::temp_dir().join(format!("tcp-udp-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stream.sock");
+        let capture_path = dir.join("feed.cap");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&capture_path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Unix,
+            path: path.to_string_lossy().into_owned(),
+            capture_path: capture_path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task =
+            tokio::spawn(
+                async move { StreamClient::new(config).run(|_| Ok(()), shutdown_rx).await },
+            );
+        let (mut server, _) = listener.accept().await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut server, b"one\ntwo\n")
+            .await
+            .unwrap();
+        drop(server);
+        task.await.unwrap().unwrap();
+
+        // Append a frame 300ms after the captured ones
+        let mut writer =
+            crate::capture::CaptureWriter::open(&capture_path, CaptureFormat::Raw).unwrap();
+        writer
+            .write(
+                b"three",
+                SystemTime::now() + Duration::from_millis(300),
+                None,
+            )
+            .unwrap();
+        drop(writer);
+
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Replay,
+            path: capture_path.to_string_lossy().into_owned(),
+            replay_speed: 3.0,
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let mut received = Vec::new();
+        let started = Instant::now();
+        StreamClient::new(config)
+            .run(
+                |frame: Frame| {
+                    received.push((frame.data, frame.meta.peer.unwrap()));
+                    Ok(())
+                },
+                shutdown_rx,
+            )
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(90));
+        let peer = capture_path.to_string_lossy().into_owned();
+        assert_eq!(
+            received,
+            vec![
+                (b"one".to_vec(), peer.clone()),
+                (b"two".to_vec(), peer.clone()),
+                (b"three".to_vec(), peer),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_tail_resumes_from_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("tcp-udp-file-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("device.log");
+        let append = |data: &str| {
+            use std::io::Write;
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(data.as_bytes()).unwrap();
+        };
+        append("old\n");
+
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::File,
+            path: path.to_string_lossy().into_owned(),
+            file_checkpoint: dir.join("checkpoint").to_string_lossy().into_owned(),
+            file_poll_interval_ms: 10,
+            ..Default::default()
+        };
+        let start = |config: ConnectionConfig| {
+            let (frame_tx, frame_rx) = mpsc::unbounded_channel();
+            let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+            let task = tokio::spawn(async move {
+                StreamClient::new(config)
+                    .run(
+                        |frame: Frame| {
+                            frame_tx.send(frame.data).unwrap();
+                            Ok(())
+                        },
+                        shutdown_rx,
+                    )
+                    .await
+            });
+            (frame_rx, shutdown_tx, task)
+        };
+        async fn recv(frame_rx: &mut mpsc::UnboundedReceiver<Vec<u8>>) -> Vec<u8> {
+            tokio::time::timeout(Duration::from_secs(5), frame_rx.recv())
+                .await
+                .unwrap()
+                .unwrap()
+        }
+
+        // Starts at the end, skipping "old"
+        let (mut frame_rx, shutdown_tx, task) = start(config.clone());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        append("one\ntwo\n");
+        assert_eq!(recv(&mut frame_rx).await, b"one");
+        assert_eq!(recv(&mut frame_rx).await, b"two");
+        shutdown_tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+
+        // Lines appended while stopped are read after a restart
+        append("three\n");
+        let (mut frame_rx, shutdown_tx, task) = start(config.clone());
+        assert_eq!(recv(&mut frame_rx).await, b"three");
+
+        // Stopping partway through a line resumes at the start of that line
+        append("fo");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown_tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+        append("ur\n");
+        let (mut frame_rx, shutdown_tx, task) = start(config);
+        assert_eq!(recv(&mut frame_rx).await, b"four");
+        shutdown_tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rfc2217_negotiates_and_strips_iac() {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Rfc2217,
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            serial_baud: 115200,
+            serial_parity: SerialParity::None,
+            serial_flow_control: SerialFlowControl::Hardware,
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut received = Vec::new();
+            StreamClient::new(config)
+                .run(
+                    |frame: Frame| {
+                        received.push(frame.data);
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+                .map(|_| received)
+        });
+
+        // Terminal server emulator: agree to COM port control and binary, offer echo
+        let (mut server, _) = listener.accept().await.unwrap();
+        server
+            .write_all(&[
+                255, 253, 44, 255, 251, 0, 255, 253, 0, 255, 251, 3, 255, 251, 1,
+            ])
+            .await
+            .unwrap();
+        let expected: [&[u8]; 4] = [
+            &[255, 250, 44, 1, 0, 1, 0xC2, 0x00, 255, 240],
+            &[255, 250, 44, 3, 1, 255, 240],
+            &[255, 250, 44, 5, 3, 255, 240],
+            &[255, 254, 1],
+        ];
+        let contains =
+            |data: &[u8], pattern: &[u8]| data.windows(pattern.len()).any(|w| w == pattern);
+        let mut negotiation = Vec::new();
+        while !expected
+            .iter()
+            .all(|pattern| contains(&negotiation, pattern))
+        {
+            let mut buf = [0u8; 256];
+            let n = tokio::time::timeout(Duration::from_secs(5), server.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0, "client closed during negotiation");
+            negotiation.extend_from_slice(&buf[..n]);
+        }
+
+        // Confirm the baud rate, then send data with a NOP inside a line
+        server
+            .write_all(b"\xff\xfa\x2c\x65\x00\x01\xc2\x00\xff\xf0fir\xff\xf1st\r\nsecond\n")
+            .await
+            .unwrap();
+        drop(server);
+
+        let received = task.await.unwrap().unwrap();
+        assert_eq!(received, vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_telnet_refuses_options() {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = ConnectionConfig {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            telnet: true,
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut received = Vec::new();
+            StreamClient::new(config)
+                .run(
+                    |frame: Frame| {
+                        received.push(frame.data);
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+                .map(|_| received)
+        });
+
+        // WILL ECHO and DO TERMINAL-TYPE ahead of the first line
+        let (mut server, _) = listener.accept().await.unwrap();
+        server
+            .write_all(b"\xff\xfb\x01\xff\xfd\x18first\r\0second\r\n")
+            .await
+            .unwrap();
+        let mut replies = [0u8; 6];
+        tokio::time::timeout(Duration::from_secs(5), server.read_exact(&mut replies))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(replies, [255, 254, 1, 255, 252, 24]);
+        drop(server);
+
+        let received = task.await.unwrap().unwrap();
+        assert_eq!(received, vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_through_socks5_proxy() {
+        use tokio::io::AsyncWriteExt;
+
+        let (proxy_addr, _) =
+            crate::proxy::tests::spawn_socks5_proxy(Some(("user", "secret"))).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = ConnectionConfig {
+            host: "localhost".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            proxy: format!("socks5://user:secret@{proxy_addr}"),
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut received = Vec::new();
+            StreamClient::new(config)
+                .run(
+                    |frame: Frame| {
+                        received.push(frame.data);
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+                .map(|_| received)
+        });
+
+        let (mut server, _) = listener.accept().await.unwrap();
+        server.write_all(b"first\nsecond\n").await.unwrap();
+        drop(server);
+
+        let received = task.await.unwrap().unwrap();
+        assert_eq!(received, vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_udp_through_socks5_proxy() {
+        let (proxy_addr, mut relays) = crate::proxy::tests::spawn_socks5_proxy(None).await;
+        // Only the proxy can resolve the target's name
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Udp,
+            host: "gnss.invalid".to_string(),
+            port: 5000,
+            proxy: format!("socks5://{proxy_addr}"),
+            ..Default::default()
+        };
+        let client = StreamClient::new(config);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            client
+                .run(
+                    move |frame: Frame| {
+                        tx.send(frame)?;
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+        });
+
+        // The link opens the relay path with an empty datagram to the target
+        let (relay, client_udp) = relays.recv().await.unwrap();
+        let mut buf = [0u8; 128];
+        let (n, from) = tokio::time::timeout(Duration::from_secs(5), relay.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(from, client_udp);
+        let (to, payload) = proxy::parse_udp_datagram(&buf[..n]).unwrap();
+        assert_eq!(to, SocksAddr::Domain("gnss.invalid".to_string(), 5000));
+        assert!(payload.is_empty());
+
+        // The relay prefixes each datagram with its original sender; the
+        // first on the target's port is the address the proxy resolved
+        for (sender, payload) in [
+            ("192.0.2.7:6000", "other port"),
+            ("192.0.2.7:5000", "reading"),
+            ("192.0.2.8:5000", "other host"),
+            ("192.0.2.7:5000", "again"),
+        ] {
+            let sender = SocksAddr::Ip(sender.parse().unwrap());
+            let datagram = proxy::udp_datagram(&sender, payload.as_bytes()).unwrap();
+            relay.send_to(&datagram, client_udp).await.unwrap();
+        }
+        for expected in ["reading", "again"] {
+            let frame = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(frame.data, expected.as_bytes());
+            assert_eq!(frame.meta.peer.as_deref(), Some("192.0.2.7:5000"));
+        }
+
+        shutdown_tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejected_for_unsupported_links() {
+        for (protocol, proxy) in [
+            (StreamProtocol::Udp, "http://127.0.0.1:3128"),
+            (StreamProtocol::Ws, "socks5://127.0.0.1:1080"),
+            (StreamProtocol::Tcp, "gopher://127.0.0.1"),
+        ] {
+            let config = ConnectionConfig {
+                protocol,
+                proxy: proxy.to_string(),
+                ..Default::default()
+            };
+            let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+            let result = StreamClient::new(config)
+                .run(|_: Frame| Ok(()), shutdown_rx)
+                .await;
+            assert!(result.is_err(), "{proxy}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unix_stream_rate_limit_reconnects() {
+        let dir = std::env::temp_dir().join(format!("tcp-udp-rate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stream.sock");
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Unix,
+            path: path.to_string_lossy().into_owned(),
+            max_frames_per_sec: 20,
+            rate_burst_frames: 2,
+            rate_limit_policy: RateLimitPolicy::Disconnect,
+            rate_limit_cooldown_ms: 100,
+            ..Default::default()
+        };
+        let client = StreamClient::new(config);
+        let stats = client.stats();
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut received = Vec::new();
+            client
+                .run(
+                    |frame: Frame| {
+                        received.push((frame.data, frame.meta.generation));
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+                .map(|_| received)
+        });
+
+        // The third line arrives with the burst spent and closes the connection
+        let (mut server, _) = listener.accept().await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut server, b"a\nb\nc\n")
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut server, b"d\n")
+            .await
+            .unwrap();
+        drop(server);
+
+        let received = task.await.unwrap().unwrap();
+        assert_eq!(
+            received,
+            vec![(b"a".to_vec(), 1), (b"b".to_vec(), 1), (b"d".to_vec(), 2)]
+        );
+        assert_eq!(stats.snapshot().rate_limit_disconnects, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unix_stream_decompresses_gzip() {
+        use async_compression::tokio::write::GzipEncoder;
+        use tokio::io::AsyncWriteExt;
+
+        let dir = std::env::temp_dir().join(format!("tcp-udp-gzip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stream.sock");
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Unix,
+            path: path.to_string_lossy().into_owned(),
+            decompress: Compression::Gzip,
+            decompress_mode: DecompressMode::Stream,
+            decompress_max_bytes: 8,
+            oversize_policy: OversizePolicy::Disconnect,
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut received = Vec::new();
+            let result = StreamClient::new(config)
+                .run(
+                    |frame: Frame| {
+                        received.push(frame.data);
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await;
+            (received, result)
+        });
+
+        let (server, _) = listener.accept().await.unwrap();
+        let mut encoder = GzipEncoder::new(server);
+        encoder
+            .write_all(b"one\r\ntwo\nthis line is too long\n")
+            .await
+            .unwrap();
+        encoder.shutdown().await.unwrap();
+
+        let (received, result) = task.await.unwrap();
+        assert_eq!(received, vec![b"one".to_vec(), b"two".to_vec()]);
+        assert!(result.unwrap_err().to_string().contains("exceeds 8 bytes"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let client = StreamClient::new(ConnectionConfig {
+            protocol: StreamProtocol::Udp,
+            decompress: Compression::Gzip,
+            decompress_mode: DecompressMode::Stream,
+            ..Default::default()
+        });
+        assert!(client.run(|_| Ok(()), shutdown_rx).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_decompresses_frames() {
+        use async_compression::tokio::write::GzipEncoder;
+        use tokio::io::AsyncWriteExt;
+
+        async fn gzip(data: &[u8]) -> Vec<u8> {
+            let mut encoder = GzipEncoder::new(Vec::new());
+            encoder.write_all(data).await.unwrap();
+            encoder.shutdown().await.unwrap();
+            encoder.into_inner()
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = ConnectionConfig {
+            port: listener.local_addr().unwrap().port(),
+            decoder: DecoderKind::Syslog,
+            decompress: Compression::Gzip,
+            decompress_mode: DecompressMode::Frame,
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut received = Vec::new();
+            let result = StreamClient::new(config)
+                .run(
+                    |frame: Frame| {
+                        received.push(frame.data);
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await;
+            (received, result)
+        });
+
+        let (mut server, _) = listener.accept().await.unwrap();
+        for frame in [
+            gzip(b"<13>one").await,
+            b"corrupt".to_vec(),
+            gzip(b"<13>two").await,
+        ] {
+            server
+                .write_all(format!("{} ", frame.len()).as_bytes())
+                .await
+                .unwrap();
+            server.write_all(&frame).await.unwrap();
+        }
+        drop(server);
+
+        let (received, result) = task.await.unwrap();
+        result.unwrap();
+        assert_eq!(received, vec![b"<13>one".to_vec(), b"<13>two".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_frame_decompression_needs_octet_counting_on_streams() {
+        // Line framing would split compressed data at arbitrary `\n` bytes
+        let config = ConnectionConfig {
+            decompress: Compression::Gzip,
+            decompress_mode: DecompressMode::Frame,
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let err = StreamClient::new(config)
+            .run(|_: Frame| Ok(()), shutdown_rx)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("octet-counted"), "{err}");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_unixgram_abstract_receives_datagrams() {
+        let name = format!("tcp-udp-unixgram-{}", std::process::id());
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Unixgram,
+            path: format!("@{name}"),
+            ..Default::default()
+        };
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            StreamClient::new(config)
+                .run(
+                    move |frame: Frame| {
+                        tx.send(frame.data)?;
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+        });
+
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+        let sender = std::os::unix::net::UnixDatagram::unbound().unwrap();
+        let received = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let _ = sender.send_to_addr(b"hello\n", &addr);
+                tokio::select! {
+                    Some(data) = rx.recv() => break data,
+                    _ = tokio::time::sleep(std::time::Duration::from_millis(50)) => {}
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for unix datagram");
+        assert_eq!(received, b"hello");
+
+        shutdown_tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    // The handshake callback signature is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
+    #[tokio::test]
+    async fn test_websocket_forwards_text_and_binary_messages() {
+        use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let callback = |req: &Request, mut resp: Response| {
+                assert_eq!(req.headers().get("x-api-key").unwrap(), "secret");
+                assert_eq!(req.headers().get(SEC_WEBSOCKET_PROTOCOL).unwrap(), "nmea");
+                resp.headers_mut()
+                    .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("nmea"));
+                Ok(resp)
+            };
+            let mut ws = tokio_tungstenite::accept_hdr_async(tcp, callback)
+                .await
+                .unwrap();
+            ws.send(Message::Ping(b"hb".to_vec())).await.unwrap();
+            ws.send(Message::Text("$GPGLL,1".to_string()))
+                .await
+                .unwrap();
+            ws.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+            ws.send(Message::Close(None)).await.unwrap();
+            // The pong for our ping must arrive before the client's close reply
+            let mut saw_pong = false;
+            while let Some(Ok(msg)) = ws.next().await {
+                saw_pong |= matches!(msg, Message::Pong(ref p) if p == b"hb");
+            }
+            saw_pong
+        });
+
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Ws,
+            port,
+            ws_headers: [("x-api-key".to_string(), "secret".to_string())].into(),
+            ws_subprotocols: vec!["nmea".to_string()],
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let mut received = Vec::new();
+        StreamClient::new(config)
+            .run(
+                |frame: Frame| {
+                    received.push(frame.data);
+                    Ok(())
+                },
+                shutdown_rx,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(received, vec![b"$GPGLL,1".to_vec(), vec![1, 2, 3]]);
+        assert!(server.await.unwrap(), "server ping should be answered");
+    }
+
+    #[tokio::test]
+    async fn test_octet_counting_framing() {
+        let input: &[u8] = b"10 <13>hello\n\n<14>plain line\r\n5 <15>x";
+        let mut reader = BufReader::new(input);
+        let mut frames = vec![];
+        let unlimited = limit(usize::MAX, OversizePolicy::Disconnect);
+        while let Some(frame) = next_frame(&mut reader, Framing::OctetCounting, &unlimited, None)
+            .await
+            .unwrap()
+        {
+            frames.push(frame);
+        }
+        assert_eq!(
+            frames,
+            vec![
+                b"<13>hello\n".to_vec(),
+                b"".to_vec(),
+                b"<14>plain line".to_vec(),
+                b"<15>x".to_vec(),
+            ]
+        );
+
+        let mut reader = BufReader::new(&b"99999999 <13>x"[..]);
+        let limit = limit(1024 * 1024, OversizePolicy::Disconnect);
+        assert!(
+            next_frame(&mut reader, Framing::OctetCounting, &limit, None)
+                .await
+                .is_err()
+        );
+        assert_eq!(limit.stats.snapshot().oversize_disconnects, 1);
+    }
+
+    fn limit(max_len: usize, policy: OversizePolicy) -> FrameLimit {
+        FrameLimit {
+            max_len,
+            policy,
+            stats: Arc::default(),
+        }
+    }
+
+    async fn read_all(input: &[u8], framing: Framing, limit: &FrameLimit) -> Vec<Vec<u8>> {
+        let mut reader = BufReader::with_capacity(4, input);
+        let mut frames = vec![];
+        while let Some(frame) = next_frame(&mut reader, framing, limit, None).await.unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frames() {
+        let input = "short\r\nfar too long a line\nok\nGrüße!\n".as_bytes();
+
+        let discard = limit(6, OversizePolicy::Discard);
+        assert_eq!(
+            read_all(input, Framing::Lines, &discard).await,
+            vec![b"short".to_vec(), b"ok".to_vec()]
+        );
+        assert_eq!(discard.stats.snapshot().frames_discarded, 2);
+
+        // Truncation does not split the UTF-8 sequence for `ü`
+        let truncate = limit(3, OversizePolicy::Truncate);
+        assert_eq!(
+            read_all(input, Framing::Lines, &truncate).await,
+            vec![
+                b"sho".to_vec(),
+                b"far".to_vec(),
+                b"ok".to_vec(),
+                b"Gr".to_vec()
+            ]
+        );
+        assert_eq!(truncate.stats.snapshot().frames_truncated, 3);
+
+        let octets = b"12 <13>too long3 <1>4 <2>x";
+        assert_eq!(
+            read_all(
+                octets,
+                Framing::OctetCounting,
+                &limit(4, OversizePolicy::Discard)
+            )
+            .await,
+            vec![b"<1>".to_vec(), b"<2>x".to_vec()]
+        );
+
+        let disconnect = limit(6, OversizePolicy::Disconnect);
+        let mut reader = BufReader::new(input);
+        assert!(next_frame(&mut reader, Framing::Lines, &disconnect, None)
+            .await
+            .is_ok());
+        assert!(next_frame(&mut reader, Framing::Lines, &disconnect, None)
+            .await
+            .is_err());
+        assert_eq!(disconnect.stats.snapshot().oversize_disconnects, 1);
+    }
+
+    #[test]
+    fn test_split_lines_across_chunks() {
+        let mut lines = LineSplitter::new(limit(1024, OversizePolicy::Disconnect));
+        assert_eq!(
+            lines.push(b"{\"a\":1}\r\n{\"b\"").unwrap(),
+            vec![b"{\"a\":1}".to_vec()]
+        );
+        assert_eq!(lines.push(b":2}\n").unwrap(), vec![b"{\"b\":2}".to_vec()]);
+        assert_eq!(lines.finish(), None);
+
+        let mut lines = LineSplitter::new(limit(4, OversizePolicy::Truncate));
+        assert_eq!(lines.push(b"abc").unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(lines.push(b"def").unwrap(), vec![b"abcd".to_vec()]);
+        assert_eq!(lines.push(b"ghi\r\nxy").unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(lines.push(b"\n").unwrap(), vec![b"xy".to_vec()]);
+
+        let mut lines = LineSplitter::new(limit(4, OversizePolicy::Discard));
+        assert_eq!(
+            lines.push(b"abcdefgh\nab\r\nabcde\r\n").unwrap(),
+            vec![b"ab".to_vec()]
+        );
+        assert_eq!(lines.limit.stats.snapshot().frames_discarded, 2);
+    }
+
+    /// Serve a single raw HTTP response on `listener`, returning the request head
+    async fn serve_http_once(
+        listener: &tokio::net::TcpListener,
+        content_type: &str,
+        body: &str,
+    ) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !head.ends_with(b"\r\n\r\n") {
+            let n = socket.read(&mut buf).await.unwrap();
+            head.extend_from_slice(&buf[..n]);
+        }
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: {content_type}\r\nconnection: close\r\n\r\n{body}"
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8(head).unwrap().to_lowercase()
+    }
+
+    #[tokio::test]
+    async fn test_http_stream_forwards_ndjson_lines() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            serve_http_once(&listener, "application/x-ndjson", "{\"n\":1}\n\n{\"n\":2}").await
+        });
+
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::HttpStream,
+            port,
+            http_headers: [("x-feed".to_string(), "ais".to_string())].into(),
+            ..Default::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let mut received = Vec::new();
+        StreamClient::new(config)
+            .run(
+                |frame: Frame| {
+                    received.push(frame.data);
+                    Ok(())
+                },
+                shutdown_rx,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(received, vec![b"{\"n\":1}".to_vec(), b"{\"n\":2}".to_vec()]);
+        assert!(server.await.unwrap().contains("x-feed: ais"));
+    }
+
+    #[tokio::test]
+    async fn test_sse_maps_event_to_subject_and_resumes() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let first = serve_http_once(
+                &listener,
+                "text/event-stream",
+                "retry: 10\nid: 7\nevent: position\ndata: one\n\n",
+            )
+            .await;
+            let second = serve_http_once(&listener, "text/event-stream", "data: two\n\n").await;
+            (first, second)
+        });
+
+        let config = ConnectionConfig {
+            protocol: StreamProtocol::Sse,
+            port,
+            ..Default::default()
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            StreamClient::new(config)
+                .run(
+                    move |frame: Frame| {
+                        tx.send(frame)?;
+                        Ok(())
+                    },
+                    shutdown_rx,
+                )
+                .await
+        });
+
+        let first = rx.recv().await.unwrap();
+        assert_eq!(first.subject.as_deref(), Some("position"));
+        assert_eq!(first.data, b"one");
+        assert_eq!((first.meta.generation, first.meta.seq), (1, 1));
+        let second = rx.recv().await.unwrap();
+        assert_eq!(second.subject, None);
+        assert_eq!(second.data, b"two");
+        assert_eq!((second.meta.generation, second.meta.seq), (2, 1));
+        assert_eq!(first.meta.connection_id, second.meta.connection_id);
+
+        let (first_head, second_head) = server.await.unwrap();
+        assert!(first_head.contains("accept: text/event-stream"));
+        assert!(!first_head.contains("last-event-id"));
+        assert!(second_head.contains("last-event-id: 7"));
+
+        shutdown_tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
 }
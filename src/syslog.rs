@@ -0,0 +1,254 @@
+//! Syslog message parsing (RFC 5424 and the BSD format of RFC 3164).
+//!
+//! Both formats start with a `<PRI>` priority encoding the facility and
+//! severity. RFC 5424 messages follow it with a version, timestamp, hostname,
+//! app-name, procid, msgid and structured data; RFC 3164 messages with a
+//! `Mmm dd hh:mm:ss` timestamp, hostname and `tag[pid]:` prefix.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "security", "console", "clock", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A parsed syslog message
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyslogMessage {
+    /// Raw `<PRI>` value, `facility * 8 + severity`
+    pub priority: u8,
+    /// Facility name, e.g. `auth` or `local0`
+    pub facility: &'static str,
+    /// Severity name, e.g. `err` or `info`
+    pub severity: &'static str,
+    /// Protocol version; `None` for RFC 3164 messages
+    pub version: Option<u8>,
+    /// Timestamp as sent
+    pub timestamp: Option<String>,
+    /// Originating host
+    pub hostname: Option<String>,
+    /// Application name (the RFC 3164 tag)
+    pub app_name: Option<String>,
+    /// Process id
+    pub proc_id: Option<String>,
+    /// Message type (RFC 5424 only)
+    pub msg_id: Option<String>,
+    /// Structured data elements keyed by SD-ID, then parameter name
+    pub structured_data: BTreeMap<String, BTreeMap<String, String>>,
+    /// Free-form message text
+    pub message: String,
+}
+
+impl SyslogMessage {
+    /// Subject for the message, `syslog.<facility>.<severity>`
+    pub fn subject(&self) -> String {
+        format!("syslog.{}.{}", self.facility, self.severity)
+    }
+}
+
+/// Parse an RFC 5424 or RFC 3164 message. Returns `None` if the message has
+/// no valid `<PRI>` or its RFC 5424 header is malformed.
+pub fn parse_message(line: &str) -> Option<SyslogMessage> {
+    let rest = line.strip_prefix('<')?;
+    let end = rest.find('>').filter(|&end| (1..=3).contains(&end))?;
+    let priority: u8 = rest[..end].parse().ok().filter(|&p| p < 192)?;
+    let rest = &rest[end + 1..];
+
+    let mut message = SyslogMessage {
+        priority,
+        facility: FACILITIES[usize::from(priority / 8)],
+        severity: SEVERITIES[usize::from(priority % 8)],
+        version: None,
+        timestamp: None,
+        hostname: None,
+        app_name: None,
+        proc_id: None,
+        msg_id: None,
+        structured_data: BTreeMap::new(),
+        message: String::new(),
+    };
+    match rest.split_once(' ') {
+        Some((version, header)) if version.len() <= 2 && version.parse::<u8>().is_ok() => {
+            message.version = version.parse().ok();
+            parse_rfc5424(header, &mut message)?;
+        }
+        _ => parse_rfc3164(rest, &mut message),
+    }
+    Some(message)
+}
+
+/// Parse the RFC 5424 header after `<PRI>VERSION `
+fn parse_rfc5424(header: &str, message: &mut SyslogMessage) -> Option<()> {
+    let mut fields = header.splitn(6, ' ');
+    let mut next = || fields.next().map(nil_value);
+    message.timestamp = next()?;
+    message.hostname = next()?;
+    message.app_name = next()?;
+    message.proc_id = next()?;
+    message.msg_id = next()?;
+
+    let rest = fields.next().unwrap_or_default();
+    let rest = if let Some(rest) = rest.strip_prefix('-') {
+        rest
+    } else {
+        parse_structured_data(rest, &mut message.structured_data)?
+    };
+    let text = rest.strip_prefix(' ').unwrap_or(rest);
+    message.message = text.strip_prefix('\u{feff}').unwrap_or(text).to_string();
+    Some(())
+}
+
+/// Parse one or more `[id name="value" ...]` elements, returning the rest of
+/// the input
+fn parse_structured_data<'a>(
+    mut input: &'a str,
+    out: &mut BTreeMap<String, BTreeMap<String, String>>,
+) -> Option<&'a str> {
+    if !input.starts_with('[') {
+        return None;
+    }
+    while let Some(element) = input.strip_prefix('[') {
+        let id_end = element.find([' ', ']'])?;
+        let params = out.entry(element[..id_end].to_string()).or_default();
+        input = &element[id_end..];
+        loop {
+            input = input.trim_start_matches(' ');
+            if let Some(rest) = input.strip_prefix(']') {
+                input = rest;
+                break;
+            }
+            let (name, rest) = input.split_once("=\"")?;
+            let mut value = String::new();
+            let mut chars = rest.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (i, '"') => break i,
+                    (_, '\\') => {
+                        let (_, c) = chars.next()?;
+                        if !matches!(c, '"' | '\\' | ']') {
+                            value.push('\\');
+                        }
+                        value.push(c);
+                    }
+                    (_, c) => value.push(c),
+                }
+            };
+            params.insert(name.to_string(), value);
+            input = &rest[end + 1..];
+        }
+    }
+    Some(input)
+}
+
+/// Parse the RFC 3164 remainder after `<PRI>`. Parts that do not look like the
+/// BSD header are left in the message text.
+fn parse_rfc3164(mut rest: &str, message: &mut SyslogMessage) {
+    let timestamp = rest.get(..15).filter(|ts| {
+        MONTHS.iter().any(|month| ts.starts_with(month)) && rest.as_bytes().get(15) == Some(&b' ')
+    });
+    if let Some(timestamp) = timestamp {
+        message.timestamp = Some(timestamp.to_string());
+        rest = &rest[16..];
+        if let Some((hostname, tail)) = rest.split_once(' ') {
+            message.hostname = Some(hostname.to_string());
+            rest = tail;
+        }
+    }
+
+    let tag_end = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "-_./".contains(c)))
+        .unwrap_or(rest.len());
+    if tag_end > 0 && tag_end <= 48 {
+        let tail = &rest[tag_end..];
+        let (proc_id, tail) = match tail.strip_prefix('[').and_then(|t| t.split_once(']')) {
+            Some((pid, tail)) => (Some(pid.to_string()), tail),
+            None => (None, tail),
+        };
+        if let Some(text) = tail.strip_prefix(':') {
+            message.app_name = Some(rest[..tag_end].to_string());
+            message.proc_id = proc_id;
+            rest = text.strip_prefix(' ').unwrap_or(text);
+        }
+    }
+    message.message = rest.to_string();
+}
+
+/// Map the RFC 5424 NILVALUE `-` to `None`
+fn nil_value(field: &str) -> Option<String> {
+    (field != "-").then(|| field.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rfc5424() {
+        let message = parse_message(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 \
+             [exampleSDID@32473 iut=\"3\" eventSource=\"App\\\"lication\"][meta x=\"1\"] \
+             \u{feff}An application event",
+        )
+        .unwrap();
+        assert_eq!(message.subject(), "syslog.local4.notice");
+        assert_eq!(message.version, Some(1));
+        assert_eq!(
+            message.timestamp.as_deref(),
+            Some("2003-10-11T22:14:15.003Z")
+        );
+        assert_eq!(message.hostname.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(message.app_name.as_deref(), Some("evntslog"));
+        assert_eq!(message.proc_id, None);
+        assert_eq!(message.msg_id.as_deref(), Some("ID47"));
+        assert_eq!(
+            message.structured_data["exampleSDID@32473"]["eventSource"],
+            "App\"lication"
+        );
+        assert_eq!(message.structured_data["meta"]["x"], "1");
+        assert_eq!(message.message, "An application event");
+
+        let message = parse_message("<34>1 - - su - ID47 - 'su root' failed").unwrap();
+        assert_eq!(message.subject(), "syslog.auth.crit");
+        assert_eq!(message.timestamp, None);
+        assert!(message.structured_data.is_empty());
+        assert_eq!(message.message, "'su root' failed");
+    }
+
+    #[test]
+    fn test_parse_rfc3164() {
+        let message =
+            parse_message("<13>Feb  5 17:32:18 10.0.0.99 myapp[123]: disk almost full").unwrap();
+        assert_eq!(message.subject(), "syslog.user.notice");
+        assert_eq!(message.version, None);
+        assert_eq!(message.timestamp.as_deref(), Some("Feb  5 17:32:18"));
+        assert_eq!(message.hostname.as_deref(), Some("10.0.0.99"));
+        assert_eq!(message.app_name.as_deref(), Some("myapp"));
+        assert_eq!(message.proc_id.as_deref(), Some("123"));
+        assert_eq!(message.message, "disk almost full");
+
+        let message = parse_message("<0>kernel panic").unwrap();
+        assert_eq!(message.subject(), "syslog.kern.emerg");
+        assert_eq!(message.app_name, None);
+        assert_eq!(message.message, "kernel panic");
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        assert!(parse_message("no priority").is_none());
+        assert!(parse_message("<192>too high").is_none());
+        assert!(parse_message("<1234>too long").is_none());
+        assert!(parse_message("<34>1 2003-10-11T22:14:15Z host").is_none());
+        assert!(parse_message("<34>1 - - - - - [unterminated").is_none());
+    }
+}
@@ -0,0 +1,421 @@
+//! Following a growing local file like `tail -F` (`protocol=file`).
+//!
+//! [`FileTail`] is a byte stream that never ends: at the end of the file it
+//! waits `file_poll_interval_ms` and checks the path again, on the blocking
+//! pool. When the path names a new inode (the file was rotated), the old file
+//! is drained and the new one is read from its start; when the file shrinks
+//! (it was truncated in place), reading restarts at its start.
+//!
+//! With `file_checkpoint` set, the inode and offset of the last delivered
+//! frame are written to the checkpoint file, so a restarted link resumes
+//! where it stopped, or at the start of the file if it was rotated meanwhile.
+//! A background task writes the checkpoint at most once per
+//! [`CHECKPOINT_INTERVAL`], off the async runtime, and once more when the
+//! stream stops.
+
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Sleep;
+use tracing::{info, warn};
+
+use crate::config::FileStart;
+
+/// Minimum interval between checkpoint writes
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A read position within a particular file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TailPosition {
+    pub inode: u64,
+    pub offset: u64,
+}
+
+/// An endless byte stream over a file that may grow, be rotated or be
+/// truncated
+#[derive(Debug)]
+pub struct FileTail {
+    path: PathBuf,
+    file: tokio::fs::File,
+    inode: u64,
+    /// Offset in the current file of the next byte read
+    offset: u64,
+    poll_interval: Duration,
+    /// Wait before looking for more data after reaching the end of the file
+    sleep: Option<Pin<Box<Sleep>>>,
+    /// Check of the path running on the blocking pool once the wait is over
+    check: Option<JoinHandle<PathCheck>>,
+    /// The file that replaced the current one, opened once the current one is
+    /// drained
+    rotated: Option<(File, u64)>,
+}
+
+impl FileTail {
+    /// Open `path` at the checkpointed position if it still refers to the
+    /// same file, otherwise at `start`
+    pub fn open(
+        path: &Path,
+        start: FileStart,
+        checkpoint: Option<TailPosition>,
+        poll_interval: Duration,
+    ) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let inode = metadata.ino();
+        let offset = match checkpoint {
+            Some(position) if position.inode == inode && position.offset <= metadata.len() => {
+                position.offset
+            }
+            // Rotated or truncated since the checkpoint: everything is new
+            Some(_) => 0,
+            None if start == FileStart::Beginning => 0,
+            None => metadata.len(),
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(FileTail {
+            path: path.to_path_buf(),
+            file: tokio::fs::File::from_std(file),
+            inode,
+            offset,
+            poll_interval,
+            sleep: None,
+            check: None,
+            rotated: None,
+        })
+    }
+
+    /// Position of the next byte read
+    pub fn position(&self) -> TailPosition {
+        TailPosition {
+            inode: self.inode,
+            offset: self.offset,
+        }
+    }
+
+    /// Continue with `file` from its start
+    fn switch_to(&mut self, file: File, inode: u64) {
+        self.file = tokio::fs::File::from_std(file);
+        self.inode = inode;
+        self.offset = 0;
+    }
+
+    /// Start checking the path on the blocking pool
+    fn start_check(&mut self) {
+        let path = self.path.clone();
+        let (inode, offset) = (self.inode, self.offset);
+        self.check = Some(tokio::task::spawn_blocking(move || {
+            check_path(&path, inode, offset)
+        }));
+    }
+
+    /// Pick up a rotated or truncated file found by [`check_path`]. Returns
+    /// whether there may be data to read right away.
+    fn apply_check(&mut self, check: PathCheck) -> bool {
+        match check {
+            PathCheck::Unchanged => false,
+            PathCheck::Grown => true,
+            // Drain anything written to the old file before switching
+            PathCheck::Rotated(file, inode) => {
+                self.rotated = Some((file, inode));
+                true
+            }
+            PathCheck::Truncated(file, inode) => {
+                info!(path = %self.path.display(), "followed file truncated, reading from start");
+                self.switch_to(file, inode);
+                true
+            }
+        }
+    }
+}
+
+/// What the followed path holds after the current file reached its end
+#[derive(Debug)]
+enum PathCheck {
+    /// No new data, or the path could not be checked
+    Unchanged,
+    /// The current file has more data
+    Grown,
+    /// The path names another file, opened here
+    Rotated(File, u64),
+    /// The current file shrank and was reopened
+    Truncated(File, u64),
+}
+
+/// Compare the file at `path` with the current file's `inode` and read
+/// `offset`. Blocks on the file system.
+fn check_path(path: &Path, inode: u64, offset: u64) -> PathCheck {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        // Mid-rotation; keep the current file until the path reappears
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return PathCheck::Unchanged,
+        Err(e) => {
+            warn!(error = %e, path = %path.display(), "failed to stat followed file");
+            return PathCheck::Unchanged;
+        }
+    };
+    if metadata.ino() != inode {
+        return match File::open(path) {
+            Ok(file) => PathCheck::Rotated(file, metadata.ino()),
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "failed to open rotated file");
+                PathCheck::Unchanged
+            }
+        };
+    }
+    if metadata.len() < offset {
+        match File::open(path) {
+            Ok(file) => return PathCheck::Truncated(file, metadata.ino()),
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "failed to reopen truncated file")
+            }
+        }
+    }
+    if metadata.len() > offset {
+        PathCheck::Grown
+    } else {
+        PathCheck::Unchanged
+    }
+}
+
+impl AsyncRead for FileTail {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(sleep) = &mut this.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                this.sleep = None;
+                this.start_check();
+            }
+            if let Some(check) = &mut this.check {
+                let check = ready!(Pin::new(check).poll(cx));
+                this.check = None;
+                let more = match check {
+                    Ok(check) => this.apply_check(check),
+                    Err(e) => {
+                        warn!(error = %e, path = %this.path.display(), "followed file check failed");
+                        false
+                    }
+                };
+                if !more {
+                    this.sleep = Some(Box::pin(tokio::time::sleep(this.poll_interval)));
+                    continue;
+                }
+            }
+
+            let filled = buf.filled().len();
+            ready!(Pin::new(&mut this.file).poll_read(cx, buf))?;
+            let read = buf.filled().len() - filled;
+            if read > 0 {
+                this.offset += read as u64;
+                return Poll::Ready(Ok(()));
+            }
+
+            // End of file: move on to a rotated file, or wait for more data
+            if let Some((file, inode)) = this.rotated.take() {
+                info!(path = %this.path.display(), inode, "following rotated file");
+                this.switch_to(file, inode);
+            } else {
+                this.sleep = Some(Box::pin(tokio::time::sleep(this.poll_interval)));
+            }
+        }
+    }
+}
+
+/// Checkpoint file of a `file` stream, written by a background task
+#[derive(Debug)]
+pub struct Checkpoint {
+    positions: watch::Sender<Option<TailPosition>>,
+    task: JoinHandle<()>,
+}
+
+impl Checkpoint {
+    /// Open the checkpoint at `path`, read the position it records, if any,
+    /// and start the task that writes it
+    pub async fn load(path: &Path) -> io::Result<(Self, Option<TailPosition>)> {
+        let saved = match tokio::fs::read_to_string(path).await {
+            Ok(text) => {
+                let mut fields = text.split_whitespace().map(str::parse::<u64>);
+                match (fields.next(), fields.next()) {
+                    (Some(Ok(inode)), Some(Ok(offset))) => Some(TailPosition { inode, offset }),
+                    _ => {
+                        warn!(path = %path.display(), "ignoring malformed file checkpoint");
+                        None
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let (positions, receiver) = watch::channel(None);
+        let task = tokio::spawn(run_checkpoint(path.to_path_buf(), saved, receiver));
+        Ok((Checkpoint { positions, task }, saved))
+    }
+
+    /// Record `position`; the task writes the newest one once the interval
+    /// since its last write has passed
+    pub fn update(&self, position: TailPosition) {
+        self.positions.send_replace(Some(position));
+    }
+
+    /// Record the final `position` and wait until it is written
+    pub async fn finish(self, position: TailPosition) {
+        self.update(position);
+        let Checkpoint { positions, task } = self;
+        drop(positions);
+        let _ = task.await;
+    }
+}
+
+/// Write the newest position from `positions` whenever it changes, at most
+/// once per [`CHECKPOINT_INTERVAL`], until the sender is dropped
+async fn run_checkpoint(
+    path: PathBuf,
+    mut saved: Option<TailPosition>,
+    mut positions: watch::Receiver<Option<TailPosition>>,
+) {
+    let mut closed = false;
+    while !closed {
+        closed = positions.changed().await.is_err();
+        loop {
+            let position = *positions.borrow_and_update();
+            let Some(position) = position.filter(|&position| Some(position) != saved) else {
+                break;
+            };
+            let file = path.clone();
+            match tokio::task::spawn_blocking(move || write_checkpoint(&file, position)).await {
+                Ok(Ok(())) => saved = Some(position),
+                Ok(Err(e)) => {
+                    warn!(path = %path.display(), error = %e, "failed to save file checkpoint");
+                    break;
+                }
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "file checkpoint task failed");
+                    break;
+                }
+            }
+            if closed {
+                break;
+            }
+            let dropped = async { while positions.changed().await.is_ok() {} };
+            closed = tokio::time::timeout(CHECKPOINT_INTERVAL, dropped)
+                .await
+                .is_ok();
+        }
+    }
+}
+
+/// Replace the checkpoint file at `path` atomically
+fn write_checkpoint(path: &Path, position: TailPosition) -> io::Result<()> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    let mut file = File::create(&tmp)?;
+    writeln!(file, "{} {}", position.inode, position.offset)?;
+    file.sync_data()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    const POLL: Duration = Duration::from_millis(10);
+
+    async fn read_some(tail: &mut FileTail) -> String {
+        let mut buf = [0u8; 64];
+        let n = tokio::time::timeout(Duration::from_secs(5), tail.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    fn append(path: &Path, data: &str) {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_follows_rotation_and_truncation() {
+        let dir = std::env::temp_dir().join(format!("tcp-udp-tail-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("device.log");
+        append(&path, "old\n");
+
+        let mut tail = FileTail::open(&path, FileStart::End, None, POLL).unwrap();
+        append(&path, "one\n");
+        assert_eq!(read_some(&mut tail).await, "one\n");
+
+        // Rotate: a late write to the old file is still read before the new file
+        fs::rename(&path, dir.join("device.log.1")).unwrap();
+        append(&path, "two\n");
+        append(&dir.join("device.log.1"), "late\n");
+        assert_eq!(read_some(&mut tail).await, "late\n");
+        assert_eq!(read_some(&mut tail).await, "two\n");
+        assert_eq!(tail.position().offset, 4);
+
+        // Truncate in place; the tail notices before the file grows back
+        fs::write(&path, "").unwrap();
+        let (read, _) = tokio::join!(read_some(&mut tail), async {
+            tokio::time::sleep(POLL * 5).await;
+            append(&path, "three\n");
+        });
+        assert_eq!(read, "three\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_resume() {
+        let dir = std::env::temp_dir().join(format!("tcp-udp-checkpoint-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("device.log");
+        let checkpoint_path = dir.join("checkpoint");
+        append(&path, "one\ntwo\n");
+
+        let (checkpoint, saved) = Checkpoint::load(&checkpoint_path).await.unwrap();
+        assert_eq!(saved, None);
+        let mut tail = FileTail::open(&path, FileStart::Beginning, saved, POLL).unwrap();
+        assert_eq!(read_some(&mut tail).await, "one\ntwo\n");
+        let position = TailPosition {
+            inode: tail.position().inode,
+            offset: 4,
+        };
+        checkpoint.update(position);
+        checkpoint.finish(position).await;
+
+        // Resumes after "one" even with file_start=end
+        let (_, saved) = Checkpoint::load(&checkpoint_path).await.unwrap();
+        assert_eq!(saved, Some(position));
+        let mut tail = FileTail::open(&path, FileStart::End, saved, POLL).unwrap();
+        assert_eq!(read_some(&mut tail).await, "two\n");
+
+        // A rotated file is read from its start
+        fs::rename(&path, dir.join("device.log.1")).unwrap();
+        append(&path, "new\n");
+        let mut tail = FileTail::open(&path, FileStart::End, saved, POLL).unwrap();
+        assert_eq!(read_some(&mut tail).await, "new\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
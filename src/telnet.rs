@@ -0,0 +1,523 @@
+//! Telnet option negotiation and IAC stripping (`telnet=true`), with the
+//! RFC 2217 COM port control option for serial devices behind terminal
+//! servers (`protocol=rfc2217`).
+//!
+//! [`TelnetFilter`] removes Telnet commands from the received bytes and
+//! produces the replies the negotiation needs, following the Q method of
+//! RFC 1143 so that neither side loops on repeated requests. Options other
+//! than the ones the filter was built to accept are refused.
+//!
+//! Unless the server sends in binary mode, CR NUL (the NVT encoding of a bare
+//! carriage return) ends a line like CR LF, since feeds that send it use it
+//! as their line terminator.
+//!
+//! For RFC 2217 the client offers `COM-PORT-OPTION` and binary transmission
+//! in both directions, and once the server agrees it requests the configured
+//! baud rate, data size, parity, stop size and flow control. Settings left
+//! unchanged are sent as 0, which asks the server to report its current
+//! value; the server's replies are logged.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{debug, info};
+
+use crate::config::{ConnectionConfig, SerialFlowControl, SerialParity, SerialStopBits};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+/// Binary transmission (RFC 856)
+const OPT_BINARY: u8 = 0;
+/// Suppress go-ahead (RFC 858)
+const OPT_SGA: u8 = 3;
+/// COM port control (RFC 2217)
+const OPT_COM_PORT: u8 = 44;
+
+/// RFC 2217 client commands; the server replies with the same code + 100
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const SERVER_REPLY: u8 = 100;
+
+/// Longest subnegotiation kept; the rest is discarded
+const MAX_SUBNEGOTIATION_LEN: usize = 256;
+
+/// Size of the buffer raw bytes are read into
+const READ_BUF_LEN: usize = 8192;
+
+/// Serial port settings requested with RFC 2217, as option values where 0
+/// asks for the current setting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComPortSettings {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: u8,
+    pub stop_bits: u8,
+    pub flow_control: u8,
+}
+
+impl ComPortSettings {
+    /// The settings configured for a link
+    pub fn from_config(config: &ConnectionConfig) -> Self {
+        ComPortSettings {
+            baud: config.serial_baud,
+            data_bits: config.serial_data_bits,
+            parity: match config.serial_parity {
+                SerialParity::Unchanged => 0,
+                SerialParity::None => 1,
+                SerialParity::Odd => 2,
+                SerialParity::Even => 3,
+                SerialParity::Mark => 4,
+                SerialParity::Space => 5,
+            },
+            stop_bits: match config.serial_stop_bits {
+                SerialStopBits::Unchanged => 0,
+                SerialStopBits::One => 1,
+                SerialStopBits::Two => 2,
+                SerialStopBits::OneAndHalf => 3,
+            },
+            flow_control: match config.serial_flow_control {
+                SerialFlowControl::Unchanged => 0,
+                SerialFlowControl::None => 1,
+                SerialFlowControl::Xonxoff => 2,
+                SerialFlowControl::Hardware => 3,
+            },
+        }
+    }
+}
+
+/// RFC 1143 state of one side of an option
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum OptionState {
+    #[default]
+    No,
+    /// Requested, waiting for the peer to agree
+    WantYes,
+    Yes,
+}
+
+/// Position of the parser within a Telnet command
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParseState {
+    Data,
+    /// After IAC
+    Iac,
+    /// After IAC WILL/WONT/DO/DONT, waiting for the option
+    Negotiate(u8),
+    /// After IAC SB, waiting for the option
+    SubOption,
+    /// Inside a subnegotiation
+    Sub,
+    /// After IAC inside a subnegotiation
+    SubIac,
+}
+
+/// Strips Telnet commands from a byte stream and answers option negotiation
+#[derive(Debug)]
+pub struct TelnetFilter {
+    state: ParseState,
+    /// Options enabled on our side if the server asks
+    local_accept: &'static [u8],
+    /// Options the server may enable on its side
+    remote_accept: &'static [u8],
+    local: [OptionState; 256],
+    remote: [OptionState; 256],
+    sub_option: u8,
+    sub: Vec<u8>,
+    /// Whether the last data byte was a carriage return
+    after_cr: bool,
+    /// Serial settings sent once the server agrees to COM port control
+    com_port: Option<ComPortSettings>,
+}
+
+impl TelnetFilter {
+    fn new(
+        local_accept: &'static [u8],
+        remote_accept: &'static [u8],
+        com_port: Option<ComPortSettings>,
+    ) -> Self {
+        TelnetFilter {
+            state: ParseState::Data,
+            local_accept,
+            remote_accept,
+            local: [OptionState::No; 256],
+            remote: [OptionState::No; 256],
+            sub_option: 0,
+            sub: Vec::new(),
+            after_cr: false,
+            com_port,
+        }
+    }
+
+    /// A filter for plain Telnet feeds that refuses every option
+    pub fn refuse_all() -> Self {
+        TelnetFilter::new(&[], &[], None)
+    }
+
+    /// A filter for RFC 2217 that requests `settings` from the terminal
+    /// server
+    pub fn rfc2217(settings: ComPortSettings) -> Self {
+        TelnetFilter::new(
+            &[OPT_BINARY, OPT_COM_PORT],
+            &[OPT_BINARY, OPT_SGA],
+            Some(settings),
+        )
+    }
+
+    /// Requests to send as soon as the connection is open
+    pub fn start(&mut self, replies: &mut Vec<u8>) {
+        for &option in self.local_accept {
+            self.local[option as usize] = OptionState::WantYes;
+            replies.extend_from_slice(&[IAC, WILL, option]);
+        }
+        for &option in self.remote_accept {
+            self.remote[option as usize] = OptionState::WantYes;
+            replies.extend_from_slice(&[IAC, DO, option]);
+        }
+    }
+
+    /// Filter received bytes, appending data to `data` and negotiation
+    /// replies to `replies`
+    pub fn feed(&mut self, input: &[u8], data: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (ParseState::Data, IAC) => ParseState::Iac,
+                (ParseState::Data, 0)
+                    if self.after_cr && self.remote[OPT_BINARY as usize] != OptionState::Yes =>
+                {
+                    self.after_cr = false;
+                    data.push(b'\n');
+                    ParseState::Data
+                }
+                (ParseState::Data, _) => {
+                    self.after_cr = byte == b'\r';
+                    data.push(byte);
+                    ParseState::Data
+                }
+                (ParseState::Iac, IAC) => {
+                    self.after_cr = false;
+                    data.push(IAC);
+                    ParseState::Data
+                }
+                (ParseState::Iac, WILL | WONT | DO | DONT) => ParseState::Negotiate(byte),
+                (ParseState::Iac, SB) => ParseState::SubOption,
+                // NOP, data mark, go ahead and the other two-byte commands
+                (ParseState::Iac, _) => ParseState::Data,
+                (ParseState::Negotiate(command), option) => {
+                    self.negotiate(command, option, replies);
+                    ParseState::Data
+                }
+                (ParseState::SubOption, option) => {
+                    self.sub_option = option;
+                    self.sub.clear();
+                    ParseState::Sub
+                }
+                (ParseState::Sub, IAC) => ParseState::SubIac,
+                (ParseState::Sub, _) => {
+                    self.push_sub(byte);
+                    ParseState::Sub
+                }
+                (ParseState::SubIac, SE) => {
+                    self.subnegotiation();
+                    ParseState::Data
+                }
+                (ParseState::SubIac, IAC) => {
+                    self.push_sub(IAC);
+                    ParseState::Sub
+                }
+                // Malformed; treat the IAC as ending the subnegotiation
+                (ParseState::SubIac, _) => {
+                    self.subnegotiation();
+                    ParseState::Data
+                }
+            };
+        }
+    }
+
+    fn push_sub(&mut self, byte: u8) {
+        if self.sub.len() < MAX_SUBNEGOTIATION_LEN {
+            self.sub.push(byte);
+        }
+    }
+
+    /// Answer WILL/WONT/DO/DONT for `option`
+    fn negotiate(&mut self, command: u8, option: u8, replies: &mut Vec<u8>) {
+        debug!(command, option, "telnet negotiation received");
+        let (state, accept, yes, no) = match command {
+            DO | DONT => (
+                &mut self.local[option as usize],
+                self.local_accept,
+                WILL,
+                WONT,
+            ),
+            _ => (
+                &mut self.remote[option as usize],
+                self.remote_accept,
+                DO,
+                DONT,
+            ),
+        };
+        let enable = matches!(command, WILL | DO);
+        match (enable, *state) {
+            (true, OptionState::Yes) | (false, OptionState::No) => {}
+            // The peer agreed to our request
+            (true, OptionState::WantYes) => *state = OptionState::Yes,
+            (true, OptionState::No) if accept.contains(&option) => {
+                *state = OptionState::Yes;
+                replies.extend_from_slice(&[IAC, yes, option]);
+            }
+            (true, OptionState::No) => replies.extend_from_slice(&[IAC, no, option]),
+            // The peer refused our request
+            (false, OptionState::WantYes) => *state = OptionState::No,
+            (false, OptionState::Yes) => {
+                *state = OptionState::No;
+                replies.extend_from_slice(&[IAC, no, option]);
+            }
+        }
+
+        if command == DO
+            && option == OPT_COM_PORT
+            && self.local[option as usize] == OptionState::Yes
+        {
+            if let Some(settings) = self.com_port.take() {
+                com_port_requests(&settings, replies);
+            }
+        }
+    }
+
+    /// Handle a complete subnegotiation
+    fn subnegotiation(&mut self) {
+        if self.sub_option != OPT_COM_PORT {
+            return;
+        }
+        let Some((&code, value)) = self.sub.split_first() else {
+            return;
+        };
+        let value = value
+            .iter()
+            .take(4)
+            .fold(0u32, |acc, &b| (acc << 8) | u32::from(b));
+        let setting = match code.wrapping_sub(SERVER_REPLY) {
+            SET_BAUDRATE => "baud",
+            SET_DATASIZE => "data_bits",
+            SET_PARITY => "parity",
+            SET_STOPSIZE => "stop_bits",
+            SET_CONTROL => "flow_control",
+            _ => {
+                debug!(code, "COM port notification received");
+                return;
+            }
+        };
+        info!(setting, value, "terminal server reported serial setting");
+    }
+}
+
+/// Append the RFC 2217 requests for `settings`
+fn com_port_requests(settings: &ComPortSettings, replies: &mut Vec<u8>) {
+    let requests: [(u8, &[u8]); 5] = [
+        (SET_BAUDRATE, &settings.baud.to_be_bytes()),
+        (SET_DATASIZE, &[settings.data_bits]),
+        (SET_PARITY, &[settings.parity]),
+        (SET_STOPSIZE, &[settings.stop_bits]),
+        (SET_CONTROL, &[settings.flow_control]),
+    ];
+    for (command, value) in requests {
+        replies.extend_from_slice(&[IAC, SB, OPT_COM_PORT, command]);
+        for &byte in value {
+            replies.push(byte);
+            if byte == IAC {
+                replies.push(IAC);
+            }
+        }
+        replies.extend_from_slice(&[IAC, SE]);
+    }
+}
+
+/// A Telnet connection read as a plain byte stream: commands are removed and
+/// negotiation replies are written back on the same connection
+#[derive(Debug)]
+pub struct TelnetStream<S> {
+    inner: S,
+    filter: TelnetFilter,
+    /// Replies not yet written
+    replies: Vec<u8>,
+    /// Filtered data not yet returned
+    data: Vec<u8>,
+    consumed: usize,
+}
+
+impl<S> TelnetStream<S> {
+    /// Wrap a connected stream, queueing the filter's opening requests
+    pub fn new(inner: S, mut filter: TelnetFilter) -> Self {
+        let mut replies = Vec::new();
+        filter.start(&mut replies);
+        TelnetStream {
+            inner,
+            filter,
+            replies,
+            data: Vec::new(),
+            consumed: 0,
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TelnetStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            while !this.replies.is_empty() {
+                match Pin::new(&mut this.inner).poll_write(cx, &this.replies) {
+                    Poll::Ready(Ok(n)) => {
+                        this.replies.drain(..n);
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    // Keep reading; the write is retried on the next poll
+                    Poll::Pending => break,
+                }
+            }
+
+            if this.consumed < this.data.len() {
+                let n = buf.remaining().min(this.data.len() - this.consumed);
+                buf.put_slice(&this.data[this.consumed..this.consumed + n]);
+                this.consumed += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut raw = [0u8; READ_BUF_LEN];
+            let mut raw = ReadBuf::new(&mut raw);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut raw))?;
+            if raw.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.data.clear();
+            this.consumed = 0;
+            this.filter
+                .feed(raw.filled(), &mut this.data, &mut this.replies);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(filter: &mut TelnetFilter, input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let (mut data, mut replies) = (Vec::new(), Vec::new());
+        filter.feed(input, &mut data, &mut replies);
+        (data, replies)
+    }
+
+    #[test]
+    fn test_strips_commands_across_chunks() {
+        let mut filter = TelnetFilter::new(&[], &[], None);
+        let (data, replies) = feed(&mut filter, b"a\xff\xffb\xff\xf1c\xff");
+        assert_eq!(data, b"a\xffbc");
+        assert!(replies.is_empty());
+        // IAC SB 24 ... IAC SE split over chunks, with an escaped IAC inside
+        let (data, _) = feed(&mut filter, b"\xfa\x18x\xff");
+        assert!(data.is_empty());
+        let (data, _) = feed(&mut filter, b"\xff\xff\xf0d\n");
+        assert_eq!(data, b"d\n");
+    }
+
+    #[test]
+    fn test_cr_nul_ends_line_unless_binary() {
+        let mut filter = TelnetFilter::refuse_all();
+        let (data, _) = feed(&mut filter, b"one\r\0two\0\r");
+        assert_eq!(data, b"one\r\ntwo\0\r");
+        let (data, _) = feed(&mut filter, b"\0three\r\n");
+        assert_eq!(data, b"\nthree\r\n");
+
+        let mut filter = TelnetFilter::new(&[], &[OPT_BINARY], None);
+        let (data, _) = feed(&mut filter, &[IAC, WILL, OPT_BINARY, b'\r', 0]);
+        assert_eq!(data, b"\r\0");
+    }
+
+    #[test]
+    fn test_refuses_unknown_options_once() {
+        let mut filter = TelnetFilter::new(&[], &[OPT_SGA], None);
+        // WILL ECHO is refused, WILL SGA accepted, DO TTYPE refused
+        let (_, replies) = feed(
+            &mut filter,
+            &[IAC, WILL, 1, IAC, WILL, OPT_SGA, IAC, DO, 24],
+        );
+        assert_eq!(
+            replies,
+            [IAC, DONT, 1, IAC, DO, OPT_SGA, IAC, WONT, 24].to_vec()
+        );
+        // Repeats of an agreed option and refusals of refused ones are not answered
+        let (_, replies) = feed(&mut filter, &[IAC, WILL, OPT_SGA, IAC, WONT, 1]);
+        assert!(replies.is_empty());
+        let (_, replies) = feed(&mut filter, &[IAC, WONT, OPT_SGA]);
+        assert_eq!(replies, [IAC, DONT, OPT_SGA].to_vec());
+    }
+
+    #[test]
+    fn test_com_port_requests_after_server_agrees() {
+        let settings = ComPortSettings {
+            baud: 0x0001_C2FF,
+            data_bits: 8,
+            parity: 1,
+            stop_bits: 1,
+            flow_control: 3,
+        };
+        let mut filter = TelnetFilter::rfc2217(settings);
+        let mut opening = Vec::new();
+        filter.start(&mut opening);
+        assert_eq!(
+            opening,
+            [
+                IAC,
+                WILL,
+                OPT_BINARY,
+                IAC,
+                WILL,
+                OPT_COM_PORT,
+                IAC,
+                DO,
+                OPT_BINARY,
+                IAC,
+                DO,
+                OPT_SGA
+            ]
+            .to_vec()
+        );
+
+        let (_, replies) = feed(&mut filter, &[IAC, DO, OPT_COM_PORT]);
+        let mut expected = vec![IAC, SB, OPT_COM_PORT, SET_BAUDRATE, 0, 1, 0xC2, IAC, IAC];
+        expected.extend_from_slice(&[IAC, SE]);
+        for (command, value) in [
+            (SET_DATASIZE, 8),
+            (SET_PARITY, 1),
+            (SET_STOPSIZE, 1),
+            (SET_CONTROL, 3),
+        ] {
+            expected.extend_from_slice(&[IAC, SB, OPT_COM_PORT, command, value, IAC, SE]);
+        }
+        assert_eq!(replies, expected);
+
+        // Settings are only sent once
+        let (_, replies) = feed(
+            &mut filter,
+            &[IAC, DONT, OPT_COM_PORT, IAC, DO, OPT_COM_PORT],
+        );
+        assert_eq!(
+            replies,
+            [IAC, WONT, OPT_COM_PORT, IAC, WILL, OPT_COM_PORT].to_vec()
+        );
+    }
+}